@@ -0,0 +1,413 @@
+pub mod a51_reference;
+#[cfg(test)]
+mod test_vectors;
+
+use rhdl::{bits::bits, kernel, Bits, Digital};
+use rhdl_core::{note, note_pop_path, note_push_path, Synchronous};
+use rhdl_std::get_bit;
+
+use crate::lfsr::{lfsr_update, Lfsr, LfsrInput};
+
+/// The A5/1 keystream generator.
+///
+/// A5/1 consists of three LFSRs with a length of 19, 22 and 23 bits. Every cycle the majority of
+/// the three clocking bits is calculated and only the registers that agree with the majority are
+/// shifted. The keystream bit is the XOR of the most significant bits of all three registers.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct A51 {
+    /// The 19 bit register R1
+    r1: Lfsr<19>,
+    /// The 22 bit register R2
+    r2: Lfsr<22>,
+    /// The 23 bit register R3
+    r3: Lfsr<23>,
+}
+
+impl A51 {
+    /// Create a new A5/1 keystream generator with the standard feedback polynomials.
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        A51 {
+            // x^19 + x^18 + x^17 + x^14 + 1
+            r1: Lfsr::fibonacci(0x072000),
+            // x^22 + x^21 + 1
+            r2: Lfsr::fibonacci(0x300000),
+            // x^23 + x^22 + x^21 + x^8 + 1
+            r3: Lfsr::fibonacci(0x700080),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct A51Input {
+    /// Reset signal. Pull high to clear the registers and wait for a new key.
+    pub reset: bool,
+    /// Pulse high to start the key setup with `key` and `frame`
+    pub start: bool,
+    /// The 64 bit session key. Bit 0 is clocked in first.
+    pub key: Bits<64>,
+    /// The 22 bit frame number. Bit 0 is clocked in first.
+    pub frame: Bits<22>,
+    /// Set to high to advance the keystream by one bit
+    ///
+    /// Has no effect during the key setup.
+    pub enable: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct A51Output {
+    /// The current keystream bit
+    ///
+    /// Only valid while `ready` is high.
+    pub bit: bool,
+    /// Set to high once the key setup is done
+    ///
+    /// While this and `enable` are high, `bit` is the next keystream bit.
+    pub ready: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub enum A51StateEnum {
+    /// Waiting for a key
+    #[default]
+    Idle,
+    /// Clocking in the key bit with the given index
+    Key(u8),
+    /// Clocking in the frame number bit with the given index
+    Frame(u8),
+    /// Majority clocked mixing cycles without output
+    Mix(u8),
+    /// Producing keystream bits
+    Keystream,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct A51State {
+    /// The 19 bit register R1
+    r1: Bits<19>,
+    /// The 22 bit register R2
+    r2: Bits<22>,
+    /// The 23 bit register R3
+    r3: Bits<23>,
+    /// The key that is currently being loaded
+    key: Bits<64>,
+    /// The frame number that is currently being loaded
+    frame: Bits<22>,
+    /// The current state of the key setup
+    state: A51StateEnum,
+}
+
+impl A51State {
+    pub const fn default() -> Self {
+        A51State {
+            r1: bits::<19>(0),
+            r2: bits::<22>(0),
+            r3: bits::<23>(0),
+            key: bits::<64>(0),
+            frame: bits::<22>(0),
+            state: A51StateEnum::Idle,
+        }
+    }
+}
+
+impl Synchronous for A51 {
+    type Input = A51Input;
+    type Output = A51Output;
+    type State = A51State;
+    type Update = a51_update;
+
+    const INITIAL_STATE: Self::State = A51State::default();
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) = a51_update;
+}
+
+#[kernel]
+pub fn a51_update(params: A51, state: A51State, input: A51Input) -> (A51State, A51Output) {
+    note("input", input);
+
+    // During the key setup all registers are clocked and the key or frame bit is XORed into the
+    // feedback. Afterwards only the registers that agree with the majority are clocked.
+    let (regular_clocking, majority_clocking, shift_in) = match state.state {
+        A51StateEnum::Idle => (false, false, false),
+        A51StateEnum::Key(index) => (true, false, get_bit::<64>(state.key, index)),
+        A51StateEnum::Frame(index) => (true, false, get_bit::<22>(state.frame, index)),
+        A51StateEnum::Mix(_) => (false, true, false),
+        A51StateEnum::Keystream => (false, input.enable, false),
+    };
+
+    // The clocking bits of the three registers
+    let r1_clock = get_bit::<19>(state.r1, 8);
+    let r2_clock = get_bit::<22>(state.r2, 10);
+    let r3_clock = get_bit::<23>(state.r3, 10);
+    let majority = (r1_clock & r2_clock) | (r1_clock & r3_clock) | (r2_clock & r3_clock);
+
+    // Starting a new key setup or a reset clears all registers
+    note_push_path("r1");
+    let (r1, r1_output) = lfsr_update::<19>(
+        params.r1,
+        state.r1,
+        LfsrInput {
+            enable: regular_clocking | (majority_clocking & (r1_clock == majority)),
+            load: input.start | input.reset,
+            data: bits::<19>(0),
+            shift_in,
+        },
+    );
+    note_pop_path();
+    note_push_path("r2");
+    let (r2, r2_output) = lfsr_update::<22>(
+        params.r2,
+        state.r2,
+        LfsrInput {
+            enable: regular_clocking | (majority_clocking & (r2_clock == majority)),
+            load: input.start | input.reset,
+            data: bits::<22>(0),
+            shift_in,
+        },
+    );
+    note_pop_path();
+    note_push_path("r3");
+    let (r3, r3_output) = lfsr_update::<23>(
+        params.r3,
+        state.r3,
+        LfsrInput {
+            enable: regular_clocking | (majority_clocking & (r3_clock == majority)),
+            load: input.start | input.reset,
+            data: bits::<23>(0),
+            shift_in,
+        },
+    );
+    note_pop_path();
+
+    let next_state_enum = if input.reset {
+        A51StateEnum::Idle
+    } else if input.start {
+        A51StateEnum::Key(0)
+    } else {
+        match state.state {
+            A51StateEnum::Idle => A51StateEnum::Idle,
+            A51StateEnum::Key(index) => {
+                if index == 63 {
+                    A51StateEnum::Frame(0)
+                } else {
+                    A51StateEnum::Key(index + 1)
+                }
+            }
+            A51StateEnum::Frame(index) => {
+                if index == 21 {
+                    A51StateEnum::Mix(0)
+                } else {
+                    A51StateEnum::Frame(index + 1)
+                }
+            }
+            A51StateEnum::Mix(index) => {
+                if index == 99 {
+                    A51StateEnum::Keystream
+                } else {
+                    A51StateEnum::Mix(index + 1)
+                }
+            }
+            A51StateEnum::Keystream => A51StateEnum::Keystream,
+        }
+    };
+
+    let next_state = A51State {
+        r1,
+        r2,
+        r3,
+        key: if input.start { input.key } else { state.key },
+        frame: if input.start {
+            input.frame
+        } else {
+            state.frame
+        },
+        state: next_state_enum,
+    };
+    let output = A51Output {
+        bit: r1_output.bit ^ r2_output.bit ^ r3_output.bit,
+        ready: state.state == A51StateEnum::Keystream,
+    };
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::a51_reference::{pack_bits, A51Reference, BLOCK_LENGTH};
+    use super::test_vectors::A51_TEST_VECTORS;
+    use super::{A51Input, A51};
+    use itertools::Itertools;
+    use rhdl::{
+        bits::bits,
+        synchronous::{simulate, simulate_first_cycle, simulate_one_cycle},
+    };
+    use rhdl_core::{
+        compile_design, generate_verilog, note_init_db, note_take, KernelFnKind, Synchronous,
+    };
+    use rhdl_core::{ClockDetails, DigitalFn};
+
+    impl A51Input {
+        fn start(key: u64, frame: u32) -> Self {
+            A51Input {
+                reset: false,
+                start: true,
+                key: bits(key as u128),
+                frame: bits((frame & 0x3FFFFF) as u128),
+                enable: false,
+            }
+        }
+
+        fn next() -> Self {
+            A51Input {
+                reset: false,
+                start: false,
+                key: bits(0),
+                frame: bits(0),
+                enable: true,
+            }
+        }
+    }
+
+    /// Unpack bytes into bits, most significant bit first.
+    fn to_bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+        bytes
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+    }
+
+    #[test]
+    fn test_a51_key_setup() {
+        // Key 0x12 23 45 67 89 AB CD EF with frame 0x134
+        let input = std::iter::once(A51Input::start(0xEFCDAB8967452312, 0x134))
+            .chain(std::iter::repeat(A51Input::next()).take(400));
+
+        note_init_db();
+        let output = simulate(A51::new(), input).collect_vec();
+        let mut vcd_file = std::fs::File::create(std::env::temp_dir().join("a51.vcd")).unwrap();
+        note_take().unwrap().dump_vcd(&[], &mut vcd_file).unwrap();
+
+        // 1 cycle to clear the registers, 64 key cycles, 22 frame cycles and 100 mixing cycles
+        let first_ready = output.iter().position(|output| output.ready).unwrap();
+        assert_eq!(first_ready, 1 + 64 + 22 + 100);
+        assert!(output[first_ready..].iter().all(|output| output.ready));
+
+        let keystream = output[first_ready..]
+            .iter()
+            .map(|output| output.bit)
+            .take(114)
+            .collect_vec();
+        let expected = to_bits(&[
+            0x53, 0x4E, 0xAA, 0x58, 0x2F, 0xE8, 0x15, 0x1A, 0xB6, 0xE1, 0x85, 0x5A, 0x72, 0x8C,
+            0x00,
+        ])
+        .take(114)
+        .collect_vec();
+        assert_eq!(keystream, expected);
+    }
+
+    /// Simulate the A5/1 core and compare the first `length` keystream bits against [A51Reference].
+    ///
+    /// Panics with the cycle number of the first mismatch.
+    fn assert_keystream_matches_reference(key: u64, frame: u32, length: usize) {
+        let setup_cycles = 1 + 64 + 22 + 100;
+        let input = std::iter::once(A51Input::start(key, frame))
+            .chain(std::iter::repeat(A51Input::next()).take(setup_cycles + length - 1));
+
+        let mut reference = A51Reference::new(key, frame);
+        let mut compared = 0;
+        for (cycle, output) in simulate(A51::new(), input).enumerate() {
+            if !output.ready {
+                continue;
+            }
+            let expected = reference.next_bit();
+            assert_eq!(
+                output.bit, expected,
+                "Keystream bit {} in cycle {} does not match the reference",
+                compared, cycle
+            );
+            compared += 1;
+        }
+        assert_eq!(
+            compared, length,
+            "Only {} of {} keystream bits were ready",
+            compared, length
+        );
+    }
+
+    #[test]
+    fn test_a51_matches_reference() {
+        assert_keystream_matches_reference(0xEFCDAB8967452312, 0x134, 228);
+        assert_keystream_matches_reference(0x0000000000000000, 0x000000, 228);
+        assert_keystream_matches_reference(0xFFFFFFFFFFFFFFFF, 0x3FFFFF, 228);
+        assert_keystream_matches_reference(0x0123456789ABCDEF, 0x2AAAAA, 228);
+    }
+
+    #[test]
+    fn test_a51_pauses_without_enable() {
+        let input = std::iter::once(A51Input::start(0x0123456789ABCDEF, 0x2AAAAA)).chain(
+            [A51Input::next(), A51Input::default(), A51Input::default()]
+                .into_iter()
+                .cycle()
+                .take(3 * 300),
+        );
+
+        let keystream = simulate(A51::new(), input)
+            .zip(std::iter::once(false).chain([true, false, false].into_iter().cycle()))
+            .filter(|(output, enable)| output.ready && *enable)
+            .map(|(output, _)| output.bit)
+            .collect_vec();
+
+        assert!(keystream.len() > 100);
+        let expected = A51Reference::new(0x0123456789ABCDEF, 0x2AAAAA)
+            .take(keystream.len())
+            .collect_vec();
+        assert_eq!(keystream, expected);
+    }
+
+    #[test]
+    fn test_a51_known_answers() {
+        let clock = ClockDetails::new("clock", 1000 * 1000, 0, false);
+        for vector in A51_TEST_VECTORS {
+            let a51 = A51::new();
+            let (mut state, mut output, mut time) =
+                simulate_first_cycle(a51, A51Input::start(vector.key(), vector.frame), &clock);
+
+            let mut keystream = Vec::new();
+            while keystream.len() < 2 * BLOCK_LENGTH {
+                assert!(time < 1000 * 1000, "Keystream never got ready");
+                (state, output, time) =
+                    simulate_one_cycle(a51, A51Input::next(), state, time, &clock);
+                if output.ready {
+                    keystream.push(output.bit);
+                }
+            }
+
+            let (downlink, uplink) = keystream.split_at(BLOCK_LENGTH);
+            assert_eq!(
+                pack_bits(downlink),
+                vector.downlink,
+                "Downlink block does not match {}",
+                vector.source
+            );
+            assert_eq!(
+                pack_bits(uplink),
+                vector.uplink,
+                "Uplink block does not match {}",
+                vector.source
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_verilog() {
+        let Some(KernelFnKind::Kernel(kernel)) = <A51 as Synchronous>::Update::kernel_fn() else {
+            panic!("No kernel function found");
+        };
+        let design = &compile_design(kernel).unwrap();
+        let verilog = generate_verilog(design).unwrap();
+        let module_code = format!("{}", verilog);
+        std::fs::write(std::env::temp_dir().join("a51.v"), module_code).unwrap();
+    }
+}
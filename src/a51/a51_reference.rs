@@ -0,0 +1,147 @@
+//! Software model of A5/1 that is used as a reference for the hardware implementation.
+//!
+//! Follows the reference implementation by Marc Briceno, Ian Goldberg and David Wagner.
+
+/// Number of keystream bits in a single burst in one direction
+pub const BLOCK_LENGTH: usize = 114;
+
+const R1_MASK: u32 = 0x07FFFF;
+const R2_MASK: u32 = 0x3FFFFF;
+const R3_MASK: u32 = 0x7FFFFF;
+
+const R1_TAPS: u32 = 0x072000;
+const R2_TAPS: u32 = 0x300000;
+const R3_TAPS: u32 = 0x700080;
+
+const R1_CLOCK: u32 = 0x000100;
+const R2_CLOCK: u32 = 0x000400;
+const R3_CLOCK: u32 = 0x000400;
+
+const R1_OUT: u32 = 0x040000;
+const R2_OUT: u32 = 0x200000;
+const R3_OUT: u32 = 0x400000;
+
+/// Plain Rust A5/1 keystream generator.
+///
+/// Iterating over it yields the keystream bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct A51Reference {
+    r1: u32,
+    r2: u32,
+    r3: u32,
+}
+
+impl A51Reference {
+    /// Create a new generator and run the key setup for `key` and `frame`.
+    ///
+    /// Bit 0 of `key` and `frame` is clocked in first. Only the lower 22 bits of `frame` are used.
+    pub fn new(key: u64, frame: u32) -> Self {
+        let mut reference = A51Reference::default();
+        for i in 0..64 {
+            reference.clock_all((key >> i) & 1 == 1);
+        }
+        for i in 0..22 {
+            reference.clock_all((frame >> i) & 1 == 1);
+        }
+        for _ in 0..100 {
+            reference.clock();
+        }
+        reference
+    }
+
+    /// Generate the downlink and uplink keystream blocks for `key` and `frame`.
+    pub fn blocks(key: u64, frame: u32) -> ([bool; BLOCK_LENGTH], [bool; BLOCK_LENGTH]) {
+        let mut reference = A51Reference::new(key, frame);
+        let downlink = std::array::from_fn(|_| reference.next_bit());
+        let uplink = std::array::from_fn(|_| reference.next_bit());
+        (downlink, uplink)
+    }
+
+    /// Clock the generator once and return the next keystream bit.
+    pub fn next_bit(&mut self) -> bool {
+        self.clock();
+        self.output()
+    }
+
+    fn clock_register(register: u32, mask: u32, taps: u32, shift_in: bool) -> u32 {
+        let feedback = (register & taps).count_ones() % 2 == 1;
+        ((register << 1) & mask) | (feedback ^ shift_in) as u32
+    }
+
+    /// Clock all three registers and XOR `shift_in` into the feedback.
+    fn clock_all(&mut self, shift_in: bool) {
+        self.r1 = Self::clock_register(self.r1, R1_MASK, R1_TAPS, shift_in);
+        self.r2 = Self::clock_register(self.r2, R2_MASK, R2_TAPS, shift_in);
+        self.r3 = Self::clock_register(self.r3, R3_MASK, R3_TAPS, shift_in);
+    }
+
+    /// Clock the registers that agree with the majority of the clocking bits.
+    fn clock(&mut self) {
+        let r1_clock = self.r1 & R1_CLOCK != 0;
+        let r2_clock = self.r2 & R2_CLOCK != 0;
+        let r3_clock = self.r3 & R3_CLOCK != 0;
+        let majority = [r1_clock, r2_clock, r3_clock]
+            .into_iter()
+            .filter(|bit| *bit)
+            .count()
+            >= 2;
+        if r1_clock == majority {
+            self.r1 = Self::clock_register(self.r1, R1_MASK, R1_TAPS, false);
+        }
+        if r2_clock == majority {
+            self.r2 = Self::clock_register(self.r2, R2_MASK, R2_TAPS, false);
+        }
+        if r3_clock == majority {
+            self.r3 = Self::clock_register(self.r3, R3_MASK, R3_TAPS, false);
+        }
+    }
+
+    fn output(&self) -> bool {
+        (self.r1 & R1_OUT != 0) ^ (self.r2 & R2_OUT != 0) ^ (self.r3 & R3_OUT != 0)
+    }
+}
+
+impl Iterator for A51Reference {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        Some(self.next_bit())
+    }
+}
+
+/// Pack bits into bytes, most significant bit first.
+///
+/// The last byte is padded with zeros.
+pub fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, bit)| byte | ((*bit as u8) << (7 - i)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pack_bits, A51Reference};
+    use crate::a51::test_vectors::A51_TEST_VECTORS;
+
+    #[test]
+    fn test_reference_against_published_keystream() {
+        for vector in A51_TEST_VECTORS {
+            let (downlink, uplink) = A51Reference::blocks(vector.key(), vector.frame);
+            assert_eq!(pack_bits(&downlink), vector.downlink, "{}", vector.source);
+            assert_eq!(pack_bits(&uplink), vector.uplink, "{}", vector.source);
+        }
+    }
+
+    #[test]
+    fn test_pack_bits() {
+        assert_eq!(
+            pack_bits(&[true, false, false, false, false, false, true, true, true]),
+            [0b10000011, 0b10000000]
+        );
+    }
+}
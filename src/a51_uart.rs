@@ -0,0 +1,677 @@
+use rhdl::{bits::bits, kernel, Bits, Digital};
+use rhdl_core::{note, note_pop_path, note_push_path, Synchronous};
+use rhdl_std::{get_bit, set_bit};
+
+use crate::a51::{a51_update, A51Input, A51State, A51};
+use crate::bit_packer::{bit_packer_update, BitPacker, BitPackerInput, BitPackerState};
+use crate::bit_unpacker::{bit_unpacker_update, BitUnpacker, BitUnpackerInput, BitUnpackerState};
+use crate::buffered_uart::{
+    buffered_uart_update, BufferedUart, BufferedUartInput, BufferedUartState,
+};
+use crate::protocol::command_decoder::{
+    command_decoder_update, CommandDecoder, CommandDecoderInput, CommandDecoderState, Opcode,
+};
+use crate::serial_harness::SerialDesign;
+use crate::uart::UartConfigError;
+
+/// A5/1 encryptor that is controlled over a UART.
+///
+/// The host talks to it with the commands described in the [protocol module](crate::protocol).
+/// The key setup after `SET_FRAME` takes 187 clock cycles. Until the first key setup is done the
+/// keystream is all zeros.
+///
+/// Bytes that arrive while the encryptor is busy wait in a FIFO, so the host does not have to wait
+/// for a response before sending the next command or plaintext byte.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct A51Uart {
+    uart: BufferedUart,
+    decoder: CommandDecoder,
+    a51: A51,
+    unpacker: BitUnpacker,
+    packer: BitPacker,
+}
+
+impl A51Uart {
+    /// Create a new A51Uart with a given clock speed and bit rate.
+    #[allow(dead_code)]
+    pub fn new(clock_speed: u128, bit_rate: u128) -> Self {
+        A51Uart {
+            uart: BufferedUart::new(clock_speed, bit_rate),
+            decoder: CommandDecoder::new(),
+            a51: A51::new(),
+            unpacker: BitUnpacker::msb_first(),
+            packer: BitPacker::msb_first(),
+        }
+    }
+
+    /// Create a new A51Uart with a given clock speed and bit rate.
+    ///
+    /// Fails if the bit rate can not be generated from the clock speed.
+    pub fn try_new(clock_speed: u128, bit_rate: u128) -> Result<Self, UartConfigError> {
+        Ok(A51Uart {
+            uart: BufferedUart::try_new(clock_speed, bit_rate)?,
+            ..A51Uart::new(clock_speed, bit_rate)
+        })
+    }
+
+    /// Enable hardware flow control with the RTS and CTS lines.
+    pub fn with_flow_control(self) -> Self {
+        A51Uart {
+            uart: self.uart.with_flow_control(),
+            ..self
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct A51UartInput {
+    /// Reset signal. Pull high to forget the key, clear the errors and drop all buffered bytes.
+    pub reset: bool,
+    /// Data input line
+    pub rx: bool,
+    /// Clear to send. Only used with flow control.
+    pub cts: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct A51UartOutput {
+    /// Data output line
+    pub tx: bool,
+    /// Request to send. Low while the host should stop sending.
+    pub rts: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub enum A51UartStateEnum {
+    /// Waiting for the next command or plaintext byte
+    #[default]
+    Idle,
+    /// Waiting for the key setup to finish
+    Setup,
+    /// Encrypting `data` bit by bit with the next 8 keystream bits
+    Generate,
+    /// Waiting until the TX FIFO accepts the byte in `data`
+    Send,
+    /// Waiting until the TX FIFO accepts the status byte in `data`, followed by the given number
+    /// of bytes from `dropped`
+    Status(u8),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct A51UartState {
+    uart: BufferedUartState,
+    decoder: CommandDecoderState,
+    a51: A51State,
+    unpacker: BitUnpackerState,
+    packer: BitPackerState,
+    /// The key of the last `SET_KEY` command
+    key: Bits<64>,
+    /// Number of keystream bytes that still need to be sent after the current one
+    remaining: Bits<8>,
+    /// The byte that is currently processed or sent
+    data: Bits<8>,
+    /// Set to high once a key was set
+    key_set: bool,
+    /// Set to high when a command with a wrong checksum was received
+    checksum_error: bool,
+    /// Set to high when a command with an unknown opcode or length was received
+    unknown_command: bool,
+    /// Set to high for one cycle after a `STATUS` command to clear the overrun error of the UART
+    clear_overrun: bool,
+    /// The dropped byte count of the current `STATUS` response, shifted by the bytes already sent
+    dropped: Bits<16>,
+    /// The current state of the encryptor
+    state: A51UartStateEnum,
+}
+
+impl A51UartState {
+    pub const fn default() -> Self {
+        A51UartState {
+            uart: BufferedUartState::default(),
+            decoder: CommandDecoderState::default(),
+            a51: A51State::default(),
+            unpacker: BitUnpackerState::default(),
+            packer: BitPackerState::default(),
+            key: bits::<64>(0),
+            remaining: bits::<8>(0),
+            data: bits::<8>(0),
+            key_set: false,
+            checksum_error: false,
+            unknown_command: false,
+            clear_overrun: false,
+            dropped: bits::<16>(0),
+            state: A51UartStateEnum::Idle,
+        }
+    }
+}
+
+impl Synchronous for A51Uart {
+    type Input = A51UartInput;
+    type Output = A51UartOutput;
+    type State = A51UartState;
+    type Update = a51_uart_update;
+
+    const INITIAL_STATE: Self::State = A51UartState::default();
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) =
+        a51_uart_update;
+}
+
+/// Build the status byte as described in [Status](crate::protocol::Status).
+#[kernel]
+pub fn status_byte(
+    key_set: bool,
+    ready: bool,
+    checksum_error: bool,
+    unknown_command: bool,
+    overrun: bool,
+) -> Bits<8> {
+    let mut status = bits::<8>(0);
+    status = set_bit::<8>(status, 0, key_set);
+    status = set_bit::<8>(status, 1, ready);
+    status = set_bit::<8>(status, 2, checksum_error);
+    status = set_bit::<8>(status, 3, unknown_command);
+    status = set_bit::<8>(status, 4, overrun);
+    status
+}
+
+/// Get the most significant byte of `value`.
+#[kernel]
+pub fn high_byte(value: Bits<16>) -> Bits<8> {
+    let mut byte = bits::<8>(0);
+    byte = set_bit::<8>(byte, 0, get_bit::<16>(value, 8));
+    byte = set_bit::<8>(byte, 1, get_bit::<16>(value, 9));
+    byte = set_bit::<8>(byte, 2, get_bit::<16>(value, 10));
+    byte = set_bit::<8>(byte, 3, get_bit::<16>(value, 11));
+    byte = set_bit::<8>(byte, 4, get_bit::<16>(value, 12));
+    byte = set_bit::<8>(byte, 5, get_bit::<16>(value, 13));
+    byte = set_bit::<8>(byte, 6, get_bit::<16>(value, 14));
+    byte = set_bit::<8>(byte, 7, get_bit::<16>(value, 15));
+    byte
+}
+
+#[kernel]
+pub fn a51_uart_update(
+    params: A51Uart,
+    state: A51UartState,
+    input: A51UartInput,
+) -> (A51UartState, A51UartOutput) {
+    note("input", input);
+
+    // Received bytes wait in the RX FIFO until the encryptor is idle
+    let idle = state.state == A51UartStateEnum::Idle;
+    let sending = match state.state {
+        A51UartStateEnum::Send => true,
+        A51UartStateEnum::Status(_) => true,
+        _ => false,
+    };
+
+    note_push_path("uart");
+    let (uart_state, uart_output) = buffered_uart_update(
+        params.uart,
+        state.uart,
+        BufferedUartInput {
+            reset: input.reset,
+            rx: input.rx,
+            data: state.data,
+            write: sending,
+            read: idle,
+            cts: input.cts,
+            clear_overrun: state.clear_overrun,
+        },
+    );
+    note_pop_path();
+    note_push_path("decoder");
+    let (decoder_state, command) = command_decoder_update(
+        params.decoder,
+        state.decoder,
+        CommandDecoderInput {
+            reset: input.reset,
+            data: uart_output.received_data,
+            valid: idle & uart_output.valid,
+        },
+    );
+    note_pop_path();
+
+    let send = sending & !uart_output.tx_full;
+    let bad_checksum = command.done & command.checksum_error;
+    let unknown = command.done & !command.checksum_error & (command.opcode == Opcode::Unknown);
+    let execute = idle & command.done & !command.checksum_error;
+    let set_key = execute & (command.opcode == Opcode::SetKey);
+    let start = execute & (command.opcode == Opcode::SetFrame);
+    let encrypt = idle & command.data_valid & (command.opcode == Opcode::Encrypt);
+    let get_keystream = execute & (command.opcode == Opcode::GetKeystream) & (command.count != 0);
+    let get_status = execute & (command.opcode == Opcode::Status);
+    // ENCRYPT and GET_KEYSTREAM are answered with data, STATUS with the status byte and the
+    // dropped byte count, all other commands with a status byte. SET_FRAME is answered after the
+    // key setup.
+    let respond = idle & ((bad_checksum & (command.opcode != Opcode::Encrypt)) | unknown | set_key);
+
+    let key = if set_key { command.key } else { state.key };
+    let key_set = state.key_set | set_key;
+    let checksum_error = state.checksum_error | bad_checksum;
+    let unknown_command = state.unknown_command | unknown;
+    // An overrun in this cycle only reaches the sticky error of the UART in the next cycle
+    let overrun = uart_output.overrun_error | uart_output.overrun;
+    let generate = state.state == A51UartStateEnum::Generate;
+
+    // The unpacker loads `data` in the first cycle of Generate and then outputs one plaintext bit
+    // per keystream bit
+    note_push_path("unpacker");
+    let (unpacker_state, unpacker_output) = bit_unpacker_update(
+        params.unpacker,
+        state.unpacker,
+        BitUnpackerInput {
+            reset: input.reset,
+            data: state.data,
+            load: generate,
+            next: generate,
+        },
+    );
+    note_pop_path();
+    let shift = generate & unpacker_output.valid;
+
+    note_push_path("a51");
+    let (a51_state, a51_output) = a51_update(
+        params.a51,
+        state.a51,
+        A51Input {
+            reset: input.reset,
+            start,
+            key,
+            frame: command.frame,
+            enable: shift,
+        },
+    );
+    note_pop_path();
+    note_push_path("packer");
+    let (packer_state, packer_output) = bit_packer_update(
+        params.packer,
+        state.packer,
+        BitPackerInput {
+            reset: input.reset,
+            bit: (a51_output.bit & a51_output.ready) ^ unpacker_output.bit,
+            valid: shift,
+        },
+    );
+    note_pop_path();
+
+    let status = status_byte(
+        key_set,
+        a51_output.ready,
+        checksum_error,
+        unknown_command,
+        overrun,
+    );
+
+    let (next_state_enum, remaining, data) = match state.state {
+        A51UartStateEnum::Idle => {
+            if encrypt {
+                (A51UartStateEnum::Generate, bits::<8>(0), command.data)
+            } else if get_keystream {
+                (A51UartStateEnum::Generate, command.count - 1, bits::<8>(0))
+            } else if start {
+                (A51UartStateEnum::Setup, bits::<8>(0), state.data)
+            } else if get_status {
+                (A51UartStateEnum::Status(2), bits::<8>(0), status)
+            } else if respond {
+                (A51UartStateEnum::Send, bits::<8>(0), status)
+            } else {
+                (A51UartStateEnum::Idle, state.remaining, state.data)
+            }
+        }
+        A51UartStateEnum::Setup => {
+            if a51_output.ready {
+                (A51UartStateEnum::Send, state.remaining, status)
+            } else {
+                (A51UartStateEnum::Setup, state.remaining, state.data)
+            }
+        }
+        A51UartStateEnum::Generate => {
+            if packer_output.valid {
+                (A51UartStateEnum::Send, state.remaining, packer_output.data)
+            } else {
+                (A51UartStateEnum::Generate, state.remaining, state.data)
+            }
+        }
+        A51UartStateEnum::Send => {
+            if !send {
+                (A51UartStateEnum::Send, state.remaining, state.data)
+            } else if state.remaining != 0 {
+                (
+                    A51UartStateEnum::Generate,
+                    state.remaining - 1,
+                    bits::<8>(0),
+                )
+            } else {
+                (A51UartStateEnum::Idle, state.remaining, state.data)
+            }
+        }
+        A51UartStateEnum::Status(count) => {
+            if !send {
+                (A51UartStateEnum::Status(count), state.remaining, state.data)
+            } else if count != 0 {
+                (
+                    A51UartStateEnum::Status(count - 1),
+                    state.remaining,
+                    high_byte(state.dropped),
+                )
+            } else {
+                (A51UartStateEnum::Idle, state.remaining, state.data)
+            }
+        }
+    };
+    let dropped = if get_status {
+        uart_output.dropped
+    } else if send {
+        state.dropped << bits::<16>(8)
+    } else {
+        state.dropped
+    };
+
+    let next_state = if input.reset {
+        A51UartState {
+            uart: uart_state,
+            decoder: decoder_state,
+            a51: a51_state,
+            unpacker: unpacker_state,
+            packer: packer_state,
+            key: bits::<64>(0),
+            remaining: bits::<8>(0),
+            data: state.data,
+            key_set: false,
+            checksum_error: false,
+            unknown_command: false,
+            clear_overrun: false,
+            dropped: bits::<16>(0),
+            state: A51UartStateEnum::Idle,
+        }
+    } else {
+        A51UartState {
+            uart: uart_state,
+            decoder: decoder_state,
+            a51: a51_state,
+            unpacker: unpacker_state,
+            packer: packer_state,
+            key,
+            remaining,
+            data,
+            key_set,
+            // Reading the status clears the errors
+            checksum_error: checksum_error & !get_status,
+            unknown_command: unknown_command & !get_status,
+            clear_overrun: get_status,
+            dropped,
+            state: next_state_enum,
+        }
+    };
+    let output = A51UartOutput {
+        tx: uart_output.tx,
+        rts: uart_output.rts,
+    };
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+impl SerialDesign for A51Uart {
+    fn serial_input(rx: bool, _previous_output: &A51UartOutput) -> A51UartInput {
+        A51UartInput {
+            reset: false,
+            rx,
+            cts: true,
+        }
+    }
+
+    fn serial_output(output: &A51UartOutput) -> bool {
+        output.tx
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{A51Uart, A51UartInput};
+    use crate::a51::a51_reference::{pack_bits, A51Reference};
+    use crate::protocol::{Command, Status};
+    use crate::serial_harness::{deserialize, Received, SerialHarness};
+    use itertools::Itertools;
+    use rhdl::synchronous::simulate;
+    use rhdl_core::{note_init_db, note_take};
+    use rhdl_fpga::{make_constrained_verilog, Constraint};
+
+    #[test]
+    fn synthesize_for_fpga() {
+        let a51_uart = A51Uart::new(19200 /*12000000*/, 9600);
+        let constraints = Vec::new();
+        let top = make_constrained_verilog(
+            a51_uart,
+            constraints,
+            Constraint::Location(rhdl_fpga::bsp::alchitry::cu::BASE_CLOCK_100MHZ_LOCATION),
+        )
+        .unwrap();
+        let pcf = top.pcf().unwrap();
+        std::fs::write(std::env::temp_dir().join("a51_uart.v"), &top.module).unwrap();
+        std::fs::write(std::env::temp_dir().join("a51_uart.pcf"), &pcf).unwrap();
+        eprintln!("{}", top.module);
+    }
+
+    #[test]
+    fn test_a51_uart_encrypts() {
+        let bitlength = 4;
+        let key: u64 = 0xEFCDAB8967452312;
+        let frame: u32 = 0x134;
+        let plaintext = b"Hello A5/1";
+
+        let mut bad_checksum = Command::SetKey(0).encode();
+        *bad_checksum.last_mut().unwrap() ^= 0xFF;
+        let commands = [
+            Command::SetKey(key).encode(),
+            Command::SetFrame(frame).encode(),
+            Command::Encrypt(plaintext.to_vec()).encode(),
+            Command::GetKeystream(4).encode(),
+            Command::Status.encode(),
+            bad_checksum,
+            // Unknown opcode
+            vec![0x42, 0x00, 0x42],
+            Command::Status.encode(),
+            Command::Status.encode(),
+        ];
+        let messages = commands.iter().map(Vec::as_slice).collect_vec();
+
+        // Wait for the response of every command before sending the next one
+        note_init_db();
+        let received = SerialHarness::new(bitlength)
+            .with_gap(300)
+            .transfer(A51Uart::new(9600 * bitlength as u128, 9600), &messages);
+        let mut vcd_file =
+            std::fs::File::create(std::env::temp_dir().join("a51_uart.vcd")).unwrap();
+        note_take().unwrap().dump_vcd(&[], &mut vcd_file).unwrap();
+
+        let keystream = pack_bits(
+            &A51Reference::new(key, frame)
+                .take((plaintext.len() + 4) * 8)
+                .collect_vec(),
+        );
+        let ciphertext = plaintext
+            .iter()
+            .zip(&keystream)
+            .map(|(plain, key)| plain ^ key)
+            .collect_vec();
+        let ready = Status::KEY_SET | Status::READY;
+        let expected = [
+            vec![Status::KEY_SET, ready],
+            ciphertext,
+            keystream[plaintext.len()..].to_vec(),
+            // STATUS also sends the dropped byte count
+            vec![
+                ready,
+                0,
+                0,
+                ready | Status::CHECKSUM_ERROR,
+                ready | Status::CHECKSUM_ERROR | Status::UNKNOWN_COMMAND,
+                ready | Status::CHECKSUM_ERROR | Status::UNKNOWN_COMMAND,
+                0,
+                0,
+                ready,
+                0,
+                0,
+            ],
+        ]
+        .concat();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_a51_uart_streams() {
+        let bitlength = 4;
+        let key: u64 = 0x0123456789ABCDEF;
+        let frame: u32 = 0x2A;
+        let plaintext = b"Streaming plaintext during the key setup";
+
+        // All commands are sent back to back without waiting for the responses
+        let message = [
+            Command::SetKey(key).encode(),
+            Command::SetFrame(frame).encode(),
+            Command::Encrypt(plaintext.to_vec()).encode(),
+        ]
+        .concat();
+        let received = SerialHarness::new(bitlength)
+            .with_gap(600)
+            .transfer(A51Uart::new(9600 * bitlength as u128, 9600), &[&message]);
+
+        let keystream = pack_bits(
+            &A51Reference::new(key, frame)
+                .take(plaintext.len() * 8)
+                .collect_vec(),
+        );
+        let ciphertext = plaintext
+            .iter()
+            .zip(&keystream)
+            .map(|(plain, key)| plain ^ key)
+            .collect_vec();
+        let expected = [
+            vec![Status::KEY_SET, Status::KEY_SET | Status::READY],
+            ciphertext,
+        ]
+        .concat();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_a51_uart_reports_dropped_bytes() {
+        let bitlength = 4;
+        // The commands after GET_KEYSTREAM arrive while the keystream is sent. Only 17 bytes fit
+        // into the RX FIFO and the receiver, the last four STATUS commands are dropped.
+        let message = [
+            Command::GetKeystream(100).encode(),
+            Command::Status.encode(),
+            Command::Status.encode(),
+            Command::Status.encode(),
+            Command::Status.encode(),
+            Command::Encrypt(b"A5".to_vec()).encode(),
+            Command::Status.encode(),
+            Command::Status.encode(),
+            Command::Status.encode(),
+            Command::Status.encode(),
+        ]
+        .concat();
+        let received = SerialHarness::new(bitlength)
+            .with_gap(6000)
+            .transfer(A51Uart::new(9600 * bitlength as u128, 9600), &[&message]);
+
+        assert_eq!(received.len(), 100 + 4 * 3 + 2);
+        let responses = received[100..112]
+            .chunks(3)
+            .map(|response| Status::decode_response(response).unwrap())
+            .collect_vec();
+        assert!(responses.iter().all(|status| status.dropped == 12));
+        // Reading the status clears the overrun error
+        assert!(responses[0].overrun);
+        assert!(responses[1..].iter().all(|status| !status.overrun));
+    }
+
+    #[test]
+    fn test_a51_uart_answers_after_key_setup() {
+        let bitlength = 4;
+        let set_frame = Command::SetFrame(0x134).encode();
+        let received = SerialHarness::new(bitlength).with_gap(300).run(
+            A51Uart::new(9600 * bitlength as u128, 9600),
+            &[&Command::SetKey(0).encode(), &set_frame],
+        );
+
+        assert_eq!(received.len(), 2);
+        // The last byte of SET_FRAME is received during its stop bit
+        let frame_received =
+            300 + 11 * 10 * bitlength + 300 + set_frame.len() * 10 * bitlength - bitlength;
+        // The status byte is only sent once the 187 cycles of key setup are done
+        assert!(received[1].cycle > frame_received + 187);
+        assert_eq!(received[1].byte, Status::KEY_SET | Status::READY);
+    }
+
+    /// Send the RX line samples to a new A51Uart and pull reset high in `reset_cycle`
+    fn run_with_reset(bitlength: usize, line: &[bool], reset_cycle: usize) -> Vec<Received> {
+        let input = line.iter().enumerate().map(|(cycle, &rx)| A51UartInput {
+            reset: cycle == reset_cycle,
+            rx,
+            cts: true,
+        });
+        let tx = simulate(A51Uart::new(9600 * bitlength as u128, 9600), input)
+            .map(|output| output.tx)
+            .collect_vec();
+        deserialize(&tx, bitlength)
+    }
+
+    #[test]
+    fn test_a51_uart_reset_during_command() {
+        let bitlength = 4;
+        let set_key = Command::SetKey(0x0123456789ABCDEF).encode();
+        let messages: [&[u8]; 2] = [&set_key[..5], &Command::Status.encode()];
+        let harness = SerialHarness::new(bitlength).with_gap(300);
+        // Reset after the first half of SET_KEY was received
+        let reset_cycle = harness.line(&messages[..1]).len() - 150;
+
+        let received = run_with_reset(bitlength, &harness.line(&messages), reset_cycle);
+
+        // STATUS is decoded as a new command instead of the rest of the key
+        assert_eq!(
+            received.iter().map(|received| received.byte).collect_vec(),
+            [0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_a51_uart_reset_during_keystream() {
+        let bitlength = 4;
+        let key: u64 = 0xEFCDAB8967452312;
+        let set_key = Command::SetKey(key).encode();
+        let messages: [&[u8]; 6] = [
+            &set_key,
+            &Command::SetFrame(0x134).encode(),
+            &Command::GetKeystream(20).encode(),
+            &set_key,
+            &Command::SetFrame(0x135).encode(),
+            &Command::GetKeystream(4).encode(),
+        ];
+        let harness = SerialHarness::new(bitlength).with_gap(300);
+        // Reset while the first keystream is sent
+        let reset_cycle = harness.line(&messages[..3]).len() - 150;
+
+        let received = run_with_reset(bitlength, &harness.line(&messages), reset_cycle);
+
+        let (before, after): (Vec<_>, Vec<_>) = received
+            .iter()
+            .partition(|received| received.cycle < reset_cycle);
+        let before = before.iter().map(|received| received.byte).collect_vec();
+        let after = after.iter().map(|received| received.byte).collect_vec();
+        let ready = Status::KEY_SET | Status::READY;
+        let keystream = pack_bits(&A51Reference::new(key, 0x134).take(20 * 8).collect_vec());
+        assert!(before.len() < 2 + 20);
+        assert_eq!(before[..2], [Status::KEY_SET, ready]);
+        // The byte that was sent during the reset is cut off
+        assert_eq!(before[2..before.len() - 1], keystream[..before.len() - 3]);
+        // The queued keystream bytes are dropped and the next key setup starts from scratch
+        let keystream = pack_bits(&A51Reference::new(key, 0x135).take(4 * 8).collect_vec());
+        assert_eq!(after, [vec![Status::KEY_SET, ready], keystream].concat());
+    }
+}
@@ -0,0 +1,172 @@
+use rhdl::{bits::bits, kernel, Bits, Digital};
+use rhdl_core::{note, Synchronous};
+use rhdl_std::set_bit;
+
+/// Collects 8 serial bits into a byte.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BitPacker {
+    /// Store the first bit in the most significant bit instead of the least significant bit
+    msb_first: bool,
+}
+
+impl BitPacker {
+    /// Create a BitPacker that stores the first bit in the most significant bit.
+    ///
+    /// This matches the byte order of the A5/1 reference keystream.
+    #[allow(dead_code)]
+    pub fn msb_first() -> Self {
+        BitPacker { msb_first: true }
+    }
+
+    /// Create a BitPacker that stores the first bit in the least significant bit.
+    ///
+    /// This matches the order in which the UART transmits bits.
+    #[allow(dead_code)]
+    pub fn lsb_first() -> Self {
+        BitPacker { msb_first: false }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BitPackerInput {
+    /// Reset signal. Pull high to drop the bits collected so far.
+    pub reset: bool,
+    /// The next bit
+    pub bit: bool,
+    /// Set to high when `bit` should be collected
+    pub valid: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BitPackerOutput {
+    /// The collected byte
+    pub data: Bits<8>,
+    /// Set to high for one cycle when the eighth bit was collected
+    pub valid: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BitPackerState {
+    /// The bits collected so far
+    data: Bits<8>,
+    /// Number of bits collected so far
+    count: u8,
+}
+
+impl BitPackerState {
+    pub const fn default() -> Self {
+        BitPackerState {
+            data: bits::<8>(0),
+            count: 0,
+        }
+    }
+}
+
+impl Synchronous for BitPacker {
+    type Input = BitPackerInput;
+    type Output = BitPackerOutput;
+    type State = BitPackerState;
+    type Update = bit_packer_update;
+
+    const INITIAL_STATE: Self::State = BitPackerState::default();
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) =
+        bit_packer_update;
+}
+
+#[kernel]
+pub fn bit_packer_update(
+    params: BitPacker,
+    state: BitPackerState,
+    input: BitPackerInput,
+) -> (BitPackerState, BitPackerOutput) {
+    note("input", input);
+
+    let index = if params.msb_first {
+        7 - state.count
+    } else {
+        state.count
+    };
+    let data = if input.valid {
+        set_bit::<8>(state.data, index, input.bit)
+    } else {
+        state.data
+    };
+    let full = input.valid & !input.reset & (state.count == 7);
+
+    let next_state = BitPackerState {
+        data,
+        count: if full | input.reset {
+            0
+        } else if input.valid {
+            state.count + 1
+        } else {
+            state.count
+        },
+    };
+    let output = BitPackerOutput { data, valid: full };
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BitPacker, BitPackerInput};
+    use itertools::Itertools;
+    use rhdl::synchronous::simulate;
+    use rhdl_core::{note_init_db, note_take};
+
+    impl BitPackerInput {
+        fn bit(bit: bool) -> Self {
+            BitPackerInput {
+                reset: false,
+                bit,
+                valid: true,
+            }
+        }
+
+        fn idle() -> Self {
+            BitPackerInput {
+                reset: false,
+                bit: false,
+                valid: false,
+            }
+        }
+    }
+
+    #[test]
+    fn test_bit_packer_msb_first() {
+        let input = [true, false, true, false, false, true, false, true]
+            .into_iter()
+            .map(BitPackerInput::bit)
+            .interleave([BitPackerInput::idle(), BitPackerInput::idle()])
+            .chain([false, false, false, false, true, true, true, true].map(BitPackerInput::bit));
+
+        note_init_db();
+        let outputs = simulate(BitPacker::msb_first(), input).collect_vec();
+        let mut vcd_file =
+            std::fs::File::create(std::env::temp_dir().join("bit_packer.vcd")).unwrap();
+        note_take().unwrap().dump_vcd(&[], &mut vcd_file).unwrap();
+
+        let bytes = outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| output.valid)
+            .map(|(cycle, output)| (cycle, output.data.0))
+            .collect_vec();
+        assert_eq!(bytes, [(9, 0b10100101), (17, 0b00001111)]);
+    }
+
+    #[test]
+    fn test_bit_packer_lsb_first() {
+        let input = [true, true, false, false, false, false, false, true].map(BitPackerInput::bit);
+
+        let outputs = simulate(BitPacker::lsb_first(), input.into_iter()).collect_vec();
+
+        assert!(outputs[..7].iter().all(|output| !output.valid));
+        assert!(outputs[7].valid);
+        assert_eq!(outputs[7].data, 0b10000011);
+    }
+}
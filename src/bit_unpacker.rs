@@ -0,0 +1,205 @@
+use rhdl::{bits::bits, kernel, Bits, Digital};
+use rhdl_core::{note, Synchronous};
+use rhdl_std::get_bit;
+
+/// Splits a byte into 8 serial bits.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BitUnpacker {
+    /// Output the most significant bit first instead of the least significant bit
+    msb_first: bool,
+}
+
+impl BitUnpacker {
+    /// Create a BitUnpacker that outputs the most significant bit first.
+    #[allow(dead_code)]
+    pub fn msb_first() -> Self {
+        BitUnpacker { msb_first: true }
+    }
+
+    /// Create a BitUnpacker that outputs the least significant bit first.
+    #[allow(dead_code)]
+    pub fn lsb_first() -> Self {
+        BitUnpacker { msb_first: false }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BitUnpackerInput {
+    /// Reset signal. Pull high to drop the bits that were not output yet.
+    pub reset: bool,
+    /// The byte to split
+    pub data: Bits<8>,
+    /// Set to high to load `data`
+    ///
+    /// Only works if the unpacker is ready
+    pub load: bool,
+    /// Set to high to advance to the next bit
+    pub next: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BitUnpackerOutput {
+    /// The current bit
+    pub bit: bool,
+    /// Set to high, when bit is valid
+    pub valid: bool,
+    /// Set to high if the unpacker is ready for the next byte
+    pub ready: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BitUnpackerState {
+    /// The byte that is being split
+    data: Bits<8>,
+    /// Number of bits that were already output
+    index: u8,
+    /// Set to high while data contains bits that were not output yet
+    loaded: bool,
+}
+
+impl BitUnpackerState {
+    pub const fn default() -> Self {
+        BitUnpackerState {
+            data: bits::<8>(0),
+            index: 0,
+            loaded: false,
+        }
+    }
+}
+
+impl Synchronous for BitUnpacker {
+    type Input = BitUnpackerInput;
+    type Output = BitUnpackerOutput;
+    type State = BitUnpackerState;
+    type Update = bit_unpacker_update;
+
+    const INITIAL_STATE: Self::State = BitUnpackerState::default();
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) =
+        bit_unpacker_update;
+}
+
+#[kernel]
+pub fn bit_unpacker_update(
+    params: BitUnpacker,
+    state: BitUnpackerState,
+    input: BitUnpackerInput,
+) -> (BitUnpackerState, BitUnpackerOutput) {
+    note("input", input);
+
+    let next_state = if input.reset {
+        BitUnpackerState {
+            data: state.data,
+            index: 0,
+            loaded: false,
+        }
+    } else if !state.loaded {
+        if input.load {
+            BitUnpackerState {
+                data: input.data,
+                index: 0,
+                loaded: true,
+            }
+        } else {
+            state
+        }
+    } else if input.next {
+        if state.index == 7 {
+            BitUnpackerState {
+                data: state.data,
+                index: 0,
+                loaded: false,
+            }
+        } else {
+            BitUnpackerState {
+                data: state.data,
+                index: state.index + 1,
+                loaded: true,
+            }
+        }
+    } else {
+        state
+    };
+
+    let index = if params.msb_first {
+        7 - state.index
+    } else {
+        state.index
+    };
+    let output = BitUnpackerOutput {
+        bit: get_bit::<8>(state.data, index),
+        valid: state.loaded,
+        ready: !state.loaded,
+    };
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BitUnpacker, BitUnpackerInput};
+    use itertools::Itertools;
+    use rhdl::{bits::bits, synchronous::simulate};
+    use rhdl_core::{note_init_db, note_take};
+
+    impl BitUnpackerInput {
+        fn load(data: u8) -> Self {
+            BitUnpackerInput {
+                reset: false,
+                data: bits(data as u128),
+                load: true,
+                next: false,
+            }
+        }
+
+        fn next() -> Self {
+            BitUnpackerInput {
+                reset: false,
+                data: bits(0),
+                load: false,
+                next: true,
+            }
+        }
+    }
+
+    #[test]
+    fn test_bit_unpacker_msb_first() {
+        let input = std::iter::once(BitUnpackerInput::load(0b10100110))
+            .chain(std::iter::repeat(BitUnpackerInput::next()).take(9));
+
+        note_init_db();
+        let outputs = simulate(BitUnpacker::msb_first(), input).collect_vec();
+        let mut vcd_file =
+            std::fs::File::create(std::env::temp_dir().join("bit_unpacker.vcd")).unwrap();
+        note_take().unwrap().dump_vcd(&[], &mut vcd_file).unwrap();
+
+        assert!(outputs[0].ready);
+        let bits = outputs[1..9]
+            .iter()
+            .inspect(|output| assert!(output.valid && !output.ready))
+            .map(|output| output.bit)
+            .collect_vec();
+        assert_eq!(bits, [true, false, true, false, false, true, true, false]);
+        assert!(outputs[9].ready);
+        assert!(!outputs[9].valid);
+    }
+
+    #[test]
+    fn test_bit_unpacker_waits_for_next() {
+        let input = [
+            BitUnpackerInput::load(0b00000001),
+            BitUnpackerInput::load(0b11111111),
+            BitUnpackerInput::next(),
+        ];
+
+        let outputs = simulate(BitUnpacker::lsb_first(), input.into_iter()).collect_vec();
+
+        // The second load is ignored, because the first byte was not consumed yet
+        assert!(outputs[1].valid);
+        assert!(outputs[1].bit);
+        assert!(outputs[2].valid);
+        assert!(!outputs[2].bit);
+    }
+}
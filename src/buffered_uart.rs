@@ -0,0 +1,338 @@
+use rhdl::{bits::bits, kernel, Bits, Digital};
+use rhdl_core::{note, note_pop_path, note_push_path, Synchronous};
+
+use crate::fifo::{fifo_output, fifo_update, Fifo, FifoInput, FifoState};
+use crate::uart::{uart_update, Uart, UartConfigError, UartInput, UartState};
+
+/// Number of bytes each FIFO can hold
+pub const FIFO_DEPTH: usize = 16;
+
+/// A Uart with a FIFO for each direction.
+///
+/// Received bytes stay in the RX FIFO until they are read. Bytes written to the TX FIFO are sent
+/// as soon as the sender is ready, so a whole burst can be written at once.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BufferedUart {
+    uart: Uart,
+    rx_fifo: Fifo<Bits<8>, FIFO_DEPTH>,
+    tx_fifo: Fifo<Bits<8>, FIFO_DEPTH>,
+}
+
+impl BufferedUart {
+    /// Create a new BufferedUart with a given clock speed and bit rate.
+    pub fn new(clock_speed: u128, bit_rate: u128) -> Self {
+        BufferedUart {
+            uart: Uart::new(clock_speed, bit_rate),
+            rx_fifo: Fifo::new(),
+            tx_fifo: Fifo::new(),
+        }
+    }
+
+    /// Create a new BufferedUart with a given clock speed and bit rate.
+    ///
+    /// Fails if the bit rate can not be generated from the clock speed, see
+    /// [check_bit_rate](crate::uart::check_bit_rate).
+    pub fn try_new(clock_speed: u128, bit_rate: u128) -> Result<Self, UartConfigError> {
+        Ok(BufferedUart {
+            uart: Uart::try_new(clock_speed, bit_rate)?,
+            ..BufferedUart::new(clock_speed, bit_rate)
+        })
+    }
+
+    /// Enable hardware flow control with the RTS and CTS lines.
+    ///
+    /// RTS drops while the RX FIFO is almost full.
+    #[allow(dead_code)]
+    pub fn with_flow_control(self) -> Self {
+        BufferedUart {
+            uart: self.uart.with_flow_control(),
+            ..self
+        }
+    }
+
+    /// Enable software flow control with XON and XOFF bytes.
+    ///
+    /// XOFF is sent while the RX FIFO is almost full.
+    #[allow(dead_code)]
+    pub fn with_software_flow_control(self) -> Self {
+        BufferedUart {
+            uart: self.uart.with_software_flow_control(),
+            ..self
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BufferedUartInput {
+    /// Reset signal. Pull high to reset the UART and empty both FIFOs.
+    pub reset: bool,
+    /// Data input line
+    pub rx: bool,
+    /// Byte to add to the TX FIFO
+    pub data: Bits<8>,
+    /// Set to high to add `data` to the TX FIFO
+    ///
+    /// Ignored if the TX FIFO is full.
+    pub write: bool,
+    /// Set to high to remove the oldest byte from the RX FIFO
+    pub read: bool,
+    /// Clear to send. Only used with flow control.
+    pub cts: bool,
+    /// Set to high to clear `overrun_error`
+    pub clear_overrun: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BufferedUartOutput {
+    /// The oldest received byte
+    pub received_data: Bits<8>,
+    /// Set to high if the RX FIFO is not empty
+    pub valid: bool,
+    /// Set to high if the RX FIFO is almost full
+    pub rx_almost_full: bool,
+    /// Set to high if the TX FIFO is full
+    pub tx_full: bool,
+    /// Set to high if the TX FIFO is empty
+    pub tx_empty: bool,
+    /// Data output line
+    pub tx: bool,
+    /// Request to send. Low while the RX FIFO is almost full.
+    pub rts: bool,
+    /// Set to high for one cycle when a received byte was dropped because the RX FIFO was full
+    pub overrun: bool,
+    /// Set to high after a received byte was dropped until it is cleared with `clear_overrun`
+    pub overrun_error: bool,
+    /// Number of dropped bytes since the last reset
+    pub dropped: Bits<16>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BufferedUartState {
+    uart: UartState,
+    rx_fifo: FifoState<Bits<8>, FIFO_DEPTH>,
+    tx_fifo: FifoState<Bits<8>, FIFO_DEPTH>,
+}
+
+impl BufferedUartState {
+    pub const fn default() -> Self {
+        BufferedUartState {
+            uart: UartState::default(),
+            rx_fifo: FifoState::default(),
+            tx_fifo: FifoState::default(),
+        }
+    }
+}
+
+impl Synchronous for BufferedUart {
+    type Input = BufferedUartInput;
+    type Output = BufferedUartOutput;
+    type State = BufferedUartState;
+    type Update = buffered_uart_update;
+
+    const INITIAL_STATE: Self::State = BufferedUartState::default();
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) =
+        buffered_uart_update;
+}
+
+#[kernel]
+pub fn buffered_uart_update(
+    params: BufferedUart,
+    state: BufferedUartState,
+    input: BufferedUartInput,
+) -> (BufferedUartState, BufferedUartOutput) {
+    note("input", input);
+
+    // The Uart sees the oldest byte of the TX FIFO and the fill level of the RX FIFO before either
+    // of them is updated
+    let tx_fifo_head = fifo_output::<Bits<8>, FIFO_DEPTH>(params.tx_fifo, state.tx_fifo);
+    let rx_fifo_level = fifo_output::<Bits<8>, FIFO_DEPTH>(params.rx_fifo, state.rx_fifo);
+    // A full RX FIFO can take a byte if one is read in the same cycle
+    let rx_ready = !rx_fifo_level.full | input.read;
+
+    note_push_path("uart");
+    let (uart_state, uart_output) = uart_update(
+        params.uart,
+        state.uart,
+        UartInput {
+            reset: input.reset,
+            rx: input.rx,
+            data: tx_fifo_head.data,
+            ninth_bit: false,
+            valid: !tx_fifo_head.empty,
+            divisor: bits::<32>(0),
+            set_divisor: false,
+            cts: input.cts,
+            rx_ready,
+            clear_overrun: input.clear_overrun,
+            rx_almost_full: rx_fifo_level.almost_full,
+        },
+    );
+    note_pop_path();
+    // The oldest byte is removed from the TX FIFO once the sender takes it
+    note_push_path("tx_fifo");
+    let (tx_fifo_state, tx_fifo_output) = fifo_update::<Bits<8>, FIFO_DEPTH>(
+        params.tx_fifo,
+        state.tx_fifo,
+        FifoInput::<Bits<8>> {
+            reset: input.reset,
+            data: input.data,
+            write: input.write,
+            read: uart_output.ready,
+        },
+    );
+    note_pop_path();
+    note_push_path("rx_fifo");
+    let (rx_fifo_state, rx_fifo_output) = fifo_update::<Bits<8>, FIFO_DEPTH>(
+        params.rx_fifo,
+        state.rx_fifo,
+        FifoInput::<Bits<8>> {
+            reset: input.reset,
+            data: uart_output.received_data,
+            write: uart_output.valid & rx_ready,
+            read: input.read,
+        },
+    );
+    note_pop_path();
+
+    let next_state = BufferedUartState {
+        uart: uart_state,
+        rx_fifo: rx_fifo_state,
+        tx_fifo: tx_fifo_state,
+    };
+    let output = BufferedUartOutput {
+        received_data: rx_fifo_output.data,
+        valid: !rx_fifo_output.empty,
+        rx_almost_full: rx_fifo_output.almost_full,
+        tx_full: tx_fifo_output.full,
+        tx_empty: tx_fifo_output.empty,
+        tx: uart_output.tx,
+        rts: uart_output.rts,
+        overrun: uart_output.overrun,
+        overrun_error: uart_output.overrun_error,
+        dropped: uart_output.dropped,
+    };
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BufferedUart, BufferedUartInput};
+    use crate::serial_harness::{deserialize, serialize};
+    use crate::uart::UartConfigError;
+    use itertools::Itertools;
+    use rhdl::{bits::bits, synchronous::simulate};
+
+    #[test]
+    fn test_buffered_uart_try_new() {
+        assert!(BufferedUart::try_new(12_000_000, 9600).is_ok());
+        assert!(matches!(
+            BufferedUart::try_new(1, 9600),
+            Err(UartConfigError::DivisorTooSmall { .. })
+        ));
+        assert!(matches!(
+            BufferedUart::try_new(9600, 0),
+            Err(UartConfigError::ZeroBitRate)
+        ));
+        assert!(matches!(
+            BufferedUart::try_new(9600 * 5, 9600 * 2),
+            Err(UartConfigError::BaudErrorTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_buffered_uart_holds_received_bytes() {
+        let buffered_uart = BufferedUart::new(9600 * 4, 9600);
+        let line = [vec![true; 8], serialize(b"Hello A5/1, hello!", 4)].concat();
+        let reads = std::iter::repeat(BufferedUartInput {
+            rx: true,
+            read: true,
+            ..Default::default()
+        })
+        .take(20);
+        let input = line
+            .iter()
+            .map(|rx| BufferedUartInput {
+                rx: *rx,
+                ..Default::default()
+            })
+            .chain(std::iter::repeat(BufferedUartInput {
+                rx: true,
+                ..Default::default()
+            }))
+            .take(line.len() + 20)
+            .chain(reads);
+        let outputs = simulate(buffered_uart, input).collect_vec();
+
+        // Nothing is read until the end, so only the first 16 bytes fit into the FIFO. The 17th
+        // waits in the receiver and the last one is dropped.
+        let waiting = &outputs[line.len() + 19];
+        assert!(waiting.valid);
+        assert!(waiting.rx_almost_full);
+        assert!(waiting.overrun_error);
+        assert_eq!(waiting.dropped, 1);
+        let received = outputs[line.len() + 20..]
+            .iter()
+            .filter(|output| output.valid)
+            .map(|output| output.received_data.0 as u8)
+            .collect_vec();
+        assert_eq!(received, b"Hello A5/1, hello");
+    }
+
+    #[test]
+    fn test_buffered_uart_drops_rts() {
+        let buffered_uart = BufferedUart::new(9600 * 4, 9600).with_flow_control();
+        let line = [vec![true; 8], serialize(b"Hello A5/1, hello!", 4)].concat();
+        let input = line
+            .iter()
+            .map(|rx| BufferedUartInput {
+                rx: *rx,
+                cts: true,
+                ..Default::default()
+            })
+            .chain(std::iter::repeat(BufferedUartInput {
+                rx: true,
+                read: true,
+                cts: true,
+                ..Default::default()
+            }))
+            .take(line.len() + 20);
+        let outputs = simulate(buffered_uart, input).collect_vec();
+
+        assert!(outputs[0].rts);
+        // RTS drops once 12 of the 16 bytes are used
+        let dropped = outputs.iter().position(|output| !output.rts).unwrap();
+        assert!(!outputs[dropped - 1].rx_almost_full);
+        assert!(outputs[dropped].rx_almost_full);
+        assert!(outputs[line.len() - 1].rx_almost_full);
+        assert!(outputs.last().unwrap().rts);
+    }
+
+    #[test]
+    fn test_buffered_uart_sends_burst() {
+        let buffered_uart = BufferedUart::new(9600 * 4, 9600);
+        let writes = b"A5/1".iter().map(|byte| BufferedUartInput {
+            rx: true,
+            data: bits(*byte as u128),
+            write: true,
+            ..Default::default()
+        });
+        let input = writes.chain(std::iter::repeat(BufferedUartInput {
+            rx: true,
+            ..Default::default()
+        }));
+        let outputs = simulate(buffered_uart, input.take(4 * 10 * 4 + 8)).collect_vec();
+
+        assert!(!outputs[1].tx_empty);
+        assert!(outputs.last().unwrap().tx_empty);
+        let tx = outputs.iter().map(|output| output.tx).collect_vec();
+        let received = deserialize(&tx, 4)
+            .iter()
+            .map(|received| received.byte)
+            .collect_vec();
+        assert_eq!(received, b"A5/1");
+    }
+}
@@ -0,0 +1,338 @@
+//! Command line interface of the host tool.
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use itertools::Itertools;
+use rhdl::{bits::bits, synchronous::simulate};
+use rhdl_core::{
+    compile_design, generate_verilog, note_init_db, note_take, DigitalFn, KernelFnKind, Synchronous,
+};
+use rhdl_fpga::{make_constrained_verilog, Constraint};
+
+use crate::a51::a51_reference::{pack_bits, A51Reference};
+use crate::a51::{A51Input, A51};
+use crate::a51_uart::A51Uart;
+use crate::protocol::Command;
+use crate::serial_harness::SerialHarness;
+use crate::uart::Uart;
+
+pub const USAGE: &str = "\
+Usage: a5-1-rhdl <command> [options]
+
+Commands:
+  keystream --key <key> --frame <frame> [--length <bytes>] [--simulate]
+      Print keystream bytes as hex
+  encrypt --key <key> --frame <frame> [--simulate] <input> <output>
+      Encrypt a file
+  decrypt --key <key> --frame <frame> [--simulate] <input> <output>
+      Decrypt a file
+  verilog <a51|uart|a51-uart> <output> [--clock-speed <hz>] [--bit-rate <baud>] [--flow-control]
+      Write Verilog for a component. The UART designs also get a .pcf file next to it.
+  vcd <a51|uart|a51-uart> <output> [--key <key>] [--frame <frame>]
+      Run an example simulation and dump the waveform
+
+Numbers can be given in decimal or in hex with a 0x prefix.
+--key is the 64 bit session key as a number with bit 0 clocked in first. The bytes of a
+published key come in that order, least significant byte first: the key 12 23 45 67 89 AB CD EF
+is passed as --key 0xEFCDAB8967452312.
+--frame is the 22 bit frame number, also with bit 0 clocked in first.
+--simulate uses the simulated hardware instead of the software model.
+--flow-control enables the RTS and CTS lines of the UART designs.
+--clock-speed defaults to 12000000 and --bit-rate to 9600.";
+
+/// Options that do not take a value
+const FLAGS: [&str; 2] = ["simulate", "flow-control"];
+
+/// Clock speed used for the UART designs in waveforms, 4 clock cycles per bit
+const VCD_CLOCK_SPEED: u128 = 4 * 9600;
+const VCD_BIT_RATE: u128 = 9600;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Parsed command line arguments
+struct Arguments {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Arguments {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut arguments = Arguments {
+            positional: Vec::new(),
+            options: HashMap::new(),
+            flags: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                arguments.positional.push(arg.clone());
+                continue;
+            };
+            if FLAGS.contains(&name) {
+                arguments.flags.push(name.to_string());
+                continue;
+            }
+            let Some(value) = args.next() else {
+                return Err(format!("Missing value for --{}", name).into());
+            };
+            arguments.options.insert(name.to_string(), value.clone());
+        }
+        Ok(arguments)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    fn number(&self, name: &str, default: Option<u128>) -> Result<u128> {
+        match (self.options.get(name), default) {
+            (Some(value), _) => parse_number(value),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err(format!("Missing option --{}", name).into()),
+        }
+    }
+
+    fn positional(&self, index: usize, name: &str) -> Result<&str> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| format!("Missing argument <{}>", name).into())
+    }
+}
+
+/// Parse a decimal number or a hex number with a 0x prefix
+fn parse_number(text: &str) -> Result<u128> {
+    let number = match text.strip_prefix("0x") {
+        Some(hex) => u128::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    number.map_err(|error| format!("Invalid number {}: {}", text, error).into())
+}
+
+/// Run the command given by the arguments without the program name
+pub fn run(args: &[String]) -> Result<()> {
+    let Some((command, args)) = args.split_first() else {
+        return Err("No command given".into());
+    };
+    let arguments = Arguments::parse(args)?;
+    match command.as_str() {
+        "keystream" => keystream(&arguments),
+        "encrypt" | "decrypt" => encrypt(&arguments),
+        "verilog" => verilog(&arguments),
+        "vcd" => vcd(&arguments),
+        _ => Err(format!("Unknown command {}", command).into()),
+    }
+}
+
+fn key_and_frame(arguments: &Arguments, default: Option<u128>) -> Result<(u64, u32)> {
+    let key = arguments.number("key", default)?;
+    let frame = arguments.number("frame", default)?;
+    if key > u64::MAX as u128 {
+        return Err("The key has to fit into 64 bits".into());
+    }
+    if frame > 0x3FFFFF {
+        return Err("The frame number has to fit into 22 bits".into());
+    }
+    Ok((key as u64, frame as u32))
+}
+
+/// Generate `length` keystream bytes with the software model or the simulated hardware
+fn generate_keystream(key: u64, frame: u32, length: usize, simulated: bool) -> Vec<u8> {
+    let bits = if simulated {
+        let start = A51Input {
+            reset: false,
+            start: true,
+            key: bits(key as u128),
+            frame: bits(frame as u128),
+            enable: false,
+        };
+        let next = A51Input {
+            reset: false,
+            start: false,
+            key: bits(0),
+            frame: bits(0),
+            enable: true,
+        };
+        let setup_cycles = 1 + 64 + 22 + 100;
+        let input =
+            std::iter::once(start).chain(std::iter::repeat(next).take(setup_cycles + length * 8));
+        simulate(A51::new(), input)
+            .filter(|output| output.ready)
+            .map(|output| output.bit)
+            .take(length * 8)
+            .collect_vec()
+    } else {
+        A51Reference::new(key, frame).take(length * 8).collect_vec()
+    };
+    pack_bits(&bits)
+}
+
+fn keystream(arguments: &Arguments) -> Result<()> {
+    let (key, frame) = key_and_frame(arguments, None)?;
+    let length = arguments.number("length", Some(15))? as usize;
+    let keystream = generate_keystream(key, frame, length, arguments.flag("simulate"));
+    println!(
+        "{}",
+        keystream
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .join("")
+    );
+    Ok(())
+}
+
+/// Encryption and decryption are the same operation for a stream cipher
+fn encrypt(arguments: &Arguments) -> Result<()> {
+    let (key, frame) = key_and_frame(arguments, None)?;
+    let input = std::fs::read(arguments.positional(0, "input")?)?;
+    let keystream = generate_keystream(key, frame, input.len(), arguments.flag("simulate"));
+    let output = input
+        .iter()
+        .zip(keystream)
+        .map(|(byte, key)| byte ^ key)
+        .collect_vec();
+    std::fs::write(arguments.positional(1, "output")?, output)?;
+    Ok(())
+}
+
+/// Write the Verilog module for the kernel of a design
+fn write_kernel_verilog<T: Synchronous>(path: &str) -> Result<()> {
+    let Some(KernelFnKind::Kernel(kernel)) = <T as Synchronous>::Update::kernel_fn() else {
+        return Err("No kernel function found".into());
+    };
+    let design = compile_design(kernel)?;
+    let verilog = generate_verilog(&design)?;
+    std::fs::write(path, format!("{}", verilog))?;
+    Ok(())
+}
+
+/// Write the Verilog top module for the FPGA and the matching pin constraints
+fn write_fpga_verilog<T: Synchronous>(design: T, path: &str) -> Result<()> {
+    let top = make_constrained_verilog(
+        design,
+        Vec::new(),
+        Constraint::Location(rhdl_fpga::bsp::alchitry::cu::BASE_CLOCK_100MHZ_LOCATION),
+    )?;
+    let pcf = top.pcf()?;
+    std::fs::write(path, &top.module)?;
+    std::fs::write(std::path::Path::new(path).with_extension("pcf"), &pcf)?;
+    Ok(())
+}
+
+fn verilog(arguments: &Arguments) -> Result<()> {
+    let component = arguments.positional(0, "component")?;
+    let output = arguments.positional(1, "output")?;
+    let clock_speed = arguments.number("clock-speed", Some(12_000_000))?;
+    let bit_rate = arguments.number("bit-rate", Some(9600))?;
+    // Without flow control the RTS and CTS pins are left unused
+    let flow_control = arguments.flag("flow-control");
+    match component {
+        "a51" => write_kernel_verilog::<A51>(output),
+        "uart" => {
+            let uart = Uart::try_new(clock_speed, bit_rate)?;
+            let uart = if flow_control {
+                uart.with_flow_control()
+            } else {
+                uart
+            };
+            write_fpga_verilog(uart, output)
+        }
+        "a51-uart" => {
+            let a51_uart = A51Uart::try_new(clock_speed, bit_rate)?;
+            let a51_uart = if flow_control {
+                a51_uart.with_flow_control()
+            } else {
+                a51_uart
+            };
+            write_fpga_verilog(a51_uart, output)
+        }
+        _ => Err(format!("Unknown component {}", component).into()),
+    }
+}
+
+fn vcd(arguments: &Arguments) -> Result<()> {
+    let component = arguments.positional(0, "component")?;
+    let output = arguments.positional(1, "output")?;
+    let (key, frame) = key_and_frame(arguments, Some(0))?;
+    let harness = SerialHarness::new((VCD_CLOCK_SPEED / VCD_BIT_RATE) as usize);
+
+    note_init_db();
+    match component {
+        "a51" => {
+            generate_keystream(key, frame, 15, true);
+        }
+        "uart" => {
+            harness.run(Uart::new(VCD_CLOCK_SPEED, VCD_BIT_RATE), &[b"A5/1"]);
+        }
+        "a51-uart" => {
+            let commands = [
+                Command::SetKey(key),
+                Command::SetFrame(frame),
+                Command::Encrypt(b"Hello A5/1".to_vec()),
+            ];
+            let messages = commands.iter().map(Command::encode).collect_vec();
+            let messages = messages.iter().map(Vec::as_slice).collect_vec();
+            harness
+                .with_gap(300)
+                .run(A51Uart::new(VCD_CLOCK_SPEED, VCD_BIT_RATE), &messages);
+        }
+        _ => return Err(format!("Unknown component {}", component).into()),
+    }
+    let Some(db) = note_take() else {
+        return Err("No simulation data recorded".into());
+    };
+    let mut vcd_file = std::fs::File::create(output)?;
+    db.dump_vcd(&[], &mut vcd_file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{generate_keystream, parse_number, Arguments};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        let arguments = Arguments::parse(&args(&[
+            "--key",
+            "0xEFCDAB8967452312",
+            "input.txt",
+            "--simulate",
+            "--frame",
+            "308",
+            "output.txt",
+        ]))
+        .unwrap();
+
+        assert_eq!(arguments.positional(0, "input").unwrap(), "input.txt");
+        assert_eq!(arguments.positional(1, "output").unwrap(), "output.txt");
+        assert!(arguments.positional(2, "extra").is_err());
+        assert_eq!(arguments.number("key", None).unwrap(), 0xEFCDAB8967452312);
+        assert_eq!(arguments.number("frame", None).unwrap(), 0x134);
+        assert_eq!(arguments.number("length", Some(15)).unwrap(), 15);
+        assert!(arguments.number("length", None).is_err());
+        assert!(arguments.flag("simulate"));
+        assert!(Arguments::parse(&args(&["--key"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("0x134").unwrap(), 0x134);
+        assert_eq!(parse_number("42").unwrap(), 42);
+        assert!(parse_number("0xZZ").is_err());
+        assert!(parse_number("-1").is_err());
+    }
+
+    #[test]
+    fn test_simulated_keystream_matches_software() {
+        let software = generate_keystream(0xEFCDAB8967452312, 0x134, 15, false);
+        let simulated = generate_keystream(0xEFCDAB8967452312, 0x134, 15, true);
+        assert_eq!(software[..4], [0x53, 0x4E, 0xAA, 0x58]);
+        assert_eq!(software, simulated);
+    }
+}
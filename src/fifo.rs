@@ -0,0 +1,308 @@
+use std::marker::PhantomData;
+
+use rhdl::{kernel, Bits, Digital};
+use rhdl_core::{note, Synchronous};
+
+/// A value that can be stored in a [Fifo]
+pub trait FifoData: Digital + Default {
+    /// The value of the slots after reset
+    const EMPTY: Self;
+}
+
+impl<const N: usize> FifoData for Bits<N> {
+    const EMPTY: Self = Bits(0);
+}
+
+impl FifoData for bool {
+    const EMPTY: Self = false;
+}
+
+/// A first in, first out queue with DEPTH slots for values of type T.
+///
+/// The output always shows the oldest value. Pull `read` high to remove it and `write` high to
+/// add a new value. Writes to a full FIFO and reads from an empty FIFO are ignored. A full FIFO
+/// can be read and written in the same cycle.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct Fifo<T: FifoData, const DEPTH: usize> {
+    /// Number of slots
+    // TODO: Figure out how to use DEPTH inside a kernel function
+    depth: usize,
+    /// `almost_full` is high if at least this many values are stored
+    almost_full_level: usize,
+    data: PhantomData<T>,
+}
+
+impl<T: FifoData, const DEPTH: usize> Fifo<T, DEPTH> {
+    /// Create a new FIFO that is almost full at three quarters of its depth.
+    pub fn new() -> Self {
+        assert!(DEPTH > 0, "A FIFO needs at least one slot");
+        Fifo {
+            depth: DEPTH,
+            almost_full_level: (DEPTH * 3).div_ceil(4),
+            data: PhantomData,
+        }
+    }
+
+    /// Set `almost_full` once `level` values are stored.
+    #[allow(dead_code)]
+    pub fn with_almost_full_level(self, level: usize) -> Self {
+        Fifo {
+            almost_full_level: level,
+            ..self
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct FifoInput<T: FifoData> {
+    /// Reset signal. Pull high to remove all values.
+    pub reset: bool,
+    /// The value to add
+    pub data: T,
+    /// Set to high to add `data`
+    pub write: bool,
+    /// Set to high to remove the oldest value
+    pub read: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct FifoOutput<T: FifoData> {
+    /// The oldest value. Only valid if the FIFO is not empty.
+    pub data: T,
+    /// Set to high if no value is stored
+    pub empty: bool,
+    /// Set to high if all slots are used
+    pub full: bool,
+    /// Set to high if the almost full level is reached
+    pub almost_full: bool,
+    /// Number of stored values
+    pub count: usize,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital)]
+pub struct FifoState<T: FifoData, const DEPTH: usize> {
+    /// The slots
+    memory: [T; DEPTH],
+    /// Slot of the oldest value
+    read_index: usize,
+    /// Slot for the next value
+    write_index: usize,
+    /// Number of stored values
+    count: usize,
+}
+
+impl<T: FifoData, const DEPTH: usize> FifoState<T, DEPTH> {
+    pub const fn default() -> Self {
+        FifoState {
+            memory: [T::EMPTY; DEPTH],
+            read_index: 0,
+            write_index: 0,
+            count: 0,
+        }
+    }
+}
+
+// Arrays only implement Default up to 32 elements
+impl<T: FifoData, const DEPTH: usize> Default for FifoState<T, DEPTH> {
+    fn default() -> Self {
+        FifoState::default()
+    }
+}
+
+impl<T: FifoData, const DEPTH: usize> Synchronous for Fifo<T, DEPTH> {
+    type Input = FifoInput<T>;
+    type Output = FifoOutput<T>;
+    type State = FifoState<T, DEPTH>;
+    type Update = fifo_update<T, DEPTH>;
+
+    const INITIAL_STATE: Self::State = FifoState::default();
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) =
+        fifo_update::<T, DEPTH>;
+}
+
+/// Get the slot after `index` in a FIFO with `depth` slots.
+#[kernel]
+pub fn next_index(index: usize, depth: usize) -> usize {
+    if index + 1 == depth {
+        0
+    } else {
+        index + 1
+    }
+}
+
+/// Get the output of a FIFO from its state.
+///
+/// The output does not depend on the input, so it can be used before the input is known.
+#[kernel]
+pub fn fifo_output<T: FifoData, const DEPTH: usize>(
+    params: Fifo<T, DEPTH>,
+    state: FifoState<T, DEPTH>,
+) -> FifoOutput<T> {
+    FifoOutput::<T> {
+        data: state.memory[state.read_index],
+        empty: state.count == 0,
+        full: state.count == params.depth,
+        almost_full: state.count >= params.almost_full_level,
+        count: state.count,
+    }
+}
+
+#[kernel]
+pub fn fifo_update<T: FifoData, const DEPTH: usize>(
+    params: Fifo<T, DEPTH>,
+    state: FifoState<T, DEPTH>,
+    input: FifoInput<T>,
+) -> (FifoState<T, DEPTH>, FifoOutput<T>) {
+    note("input", input);
+
+    let output = fifo_output::<T, DEPTH>(params, state);
+    let read = input.read & !output.empty;
+    // A full FIFO has space again if a value is read in the same cycle
+    let write = input.write & (!output.full | read);
+
+    let mut memory = state.memory;
+    memory[state.write_index] = if write {
+        input.data
+    } else {
+        state.memory[state.write_index]
+    };
+    let next_state = if input.reset {
+        FifoState::<T, DEPTH> {
+            memory,
+            read_index: 0,
+            write_index: 0,
+            count: 0,
+        }
+    } else {
+        FifoState::<T, DEPTH> {
+            memory,
+            read_index: if read {
+                next_index(state.read_index, params.depth)
+            } else {
+                state.read_index
+            },
+            write_index: if write {
+                next_index(state.write_index, params.depth)
+            } else {
+                state.write_index
+            },
+            count: if read & !write {
+                state.count - 1
+            } else if write & !read {
+                state.count + 1
+            } else {
+                state.count
+            },
+        }
+    };
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Fifo, FifoInput, FifoOutput};
+    use itertools::Itertools;
+    use rhdl::{bits::bits, synchronous::simulate, Bits};
+    use rhdl_core::{note_init_db, note_take};
+
+    fn write(data: u128) -> FifoInput<Bits<8>> {
+        FifoInput {
+            reset: false,
+            data: bits(data),
+            write: true,
+            read: false,
+        }
+    }
+
+    fn read() -> FifoInput<Bits<8>> {
+        FifoInput {
+            reset: false,
+            data: bits(0),
+            write: false,
+            read: true,
+        }
+    }
+
+    #[test]
+    fn test_fifo_keeps_order() {
+        let input = (1..=3)
+            .map(write)
+            .chain([read(), write(4), read(), read(), read(), read()]);
+
+        note_init_db();
+        let outputs = simulate(Fifo::<Bits<8>, 4>::new(), input).collect_vec();
+        let mut vcd_file = std::fs::File::create(std::env::temp_dir().join("fifo.vcd")).unwrap();
+        note_take().unwrap().dump_vcd(&[], &mut vcd_file).unwrap();
+
+        let read_values = outputs
+            .iter()
+            .skip(3)
+            .filter(|output| !output.empty)
+            .map(|output| output.data.0)
+            .collect_vec();
+        assert_eq!(read_values, [1, 2, 2, 3, 4]);
+        assert!(outputs.last().unwrap().empty);
+    }
+
+    #[test]
+    fn test_fifo_flags() {
+        let fifo = Fifo::<Bits<8>, 4>::new();
+        let input = (1..=5).map(write).chain([
+            FifoInput {
+                read: true,
+                ..write(6)
+            },
+            read(),
+            read(),
+            read(),
+            read(),
+            FifoInput::default(),
+        ]);
+        let outputs = simulate(fifo, input).collect_vec();
+
+        let flags = |output: &FifoOutput<Bits<8>>| {
+            (output.count, output.empty, output.almost_full, output.full)
+        };
+        assert_eq!(flags(&outputs[0]), (0, true, false, false));
+        assert_eq!(flags(&outputs[3]), (3, false, true, false));
+        // The fifth value is dropped
+        assert_eq!(flags(&outputs[4]), (4, false, true, true));
+        assert_eq!(flags(&outputs[5]), (4, false, true, true));
+        // Reading and writing a full FIFO at the same time removes the oldest value and appends the
+        // new one
+        assert_eq!(flags(&outputs[6]), (4, false, true, true));
+        assert_eq!(
+            outputs[6..10]
+                .iter()
+                .map(|output| output.data.0)
+                .collect_vec(),
+            [2, 3, 4, 6]
+        );
+        assert_eq!(flags(&outputs[10]), (0, true, false, false));
+    }
+
+    #[test]
+    fn test_fifo_reset() {
+        let input = (1..=3).map(write).chain([
+            FifoInput {
+                reset: true,
+                ..write(4)
+            },
+            write(5),
+            read(),
+            read(),
+        ]);
+        let outputs = simulate(Fifo::<Bits<8>, 4>::new(), input).collect_vec();
+
+        // The reset also drops the value written in the same cycle
+        assert_eq!(outputs[3].count, 3);
+        assert!(outputs[4].empty);
+        assert_eq!(outputs[5].count, 1);
+        assert_eq!(outputs[5].data, 5);
+        assert!(outputs[6].empty);
+    }
+}
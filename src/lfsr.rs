@@ -0,0 +1,218 @@
+use rhdl::{bits::bits, kernel, Bits, Digital};
+use rhdl_core::{note, Synchronous};
+use rhdl_std::{get_bit, set_bit, xor};
+
+/// How the tap mask is applied to the register
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub enum LfsrFeedback {
+    /// The parity of all tapped bits is shifted into bit 0
+    #[default]
+    Fibonacci,
+    /// The bit shifted out of the most significant bit toggles all tapped bits
+    Galois,
+}
+
+/// A linear feedback shift register with a configurable length and tap mask.
+///
+/// The register shifts towards the most significant bit. It can be used for the A5/1 registers,
+/// scramblers or PRBS generators.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct Lfsr<const N: usize> {
+    /// Tap mask
+    ///
+    /// For Fibonacci feedback these are the bits that are XORed to get the feedback bit.
+    /// For Galois feedback these are the bits that get toggled, including bit 0 for the `+ 1` term.
+    taps: Bits<N>,
+    /// How the taps are applied
+    feedback: LfsrFeedback,
+    /// Index of the most significant bit
+    // TODO: Figure out how to use N inside a kernel function
+    output_tap: u8,
+}
+
+impl<const N: usize> Lfsr<N> {
+    /// Create a new LFSR with Fibonacci feedback.
+    #[allow(dead_code)]
+    pub fn fibonacci(taps: u128) -> Self {
+        Lfsr {
+            taps: bits(taps),
+            feedback: LfsrFeedback::Fibonacci,
+            output_tap: (N - 1) as u8,
+        }
+    }
+
+    /// Create a new LFSR with Galois feedback.
+    #[allow(dead_code)]
+    pub fn galois(taps: u128) -> Self {
+        Lfsr {
+            taps: bits(taps),
+            feedback: LfsrFeedback::Galois,
+            output_tap: (N - 1) as u8,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct LfsrInput<const N: usize> {
+    /// Clock enable. The register only shifts while this is high.
+    pub enable: bool,
+    /// Set to high to load `data` into the register. Takes precedence over `enable`.
+    pub load: bool,
+    /// Value to load in parallel
+    pub data: Bits<N>,
+    /// Extra bit that is XORed into the feedback
+    pub shift_in: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct LfsrOutput<const N: usize> {
+    /// The contents of the register after this cycle
+    pub state: Bits<N>,
+    /// The most significant bit of the register after this cycle
+    pub bit: bool,
+}
+
+impl<const N: usize> Synchronous for Lfsr<N> {
+    type Input = LfsrInput<N>;
+    type Output = LfsrOutput<N>;
+    type State = Bits<N>;
+    type Update = lfsr_update<N>;
+
+    const INITIAL_STATE: Self::State = bits(0);
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) =
+        lfsr_update::<N>;
+}
+
+#[kernel]
+pub fn lfsr_update<const N: usize>(
+    params: Lfsr<N>,
+    state: Bits<N>,
+    input: LfsrInput<N>,
+) -> (Bits<N>, LfsrOutput<N>) {
+    note("input", input);
+
+    let shifted = state << bits::<N>(1);
+    let shifted_out = get_bit::<N>(state, params.output_tap);
+    let clocked = match params.feedback {
+        LfsrFeedback::Fibonacci => {
+            set_bit::<N>(shifted, 0, xor::<N>(state & params.taps) ^ input.shift_in)
+        }
+        LfsrFeedback::Galois => {
+            if shifted_out ^ input.shift_in {
+                shifted ^ params.taps
+            } else {
+                shifted
+            }
+        }
+    };
+
+    let next_state = if input.load {
+        input.data
+    } else if input.enable {
+        clocked
+    } else {
+        state
+    };
+
+    let output = LfsrOutput {
+        state: next_state,
+        bit: get_bit::<N>(next_state, params.output_tap),
+    };
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Lfsr, LfsrInput};
+    use itertools::Itertools;
+    use rhdl::{bits::bits, synchronous::simulate};
+    use rhdl_core::{note_init_db, note_take};
+
+    impl<const N: usize> LfsrInput<N> {
+        fn load(data: u128) -> Self {
+            LfsrInput {
+                enable: false,
+                load: true,
+                data: bits(data),
+                shift_in: false,
+            }
+        }
+
+        fn clock() -> Self {
+            LfsrInput {
+                enable: true,
+                load: false,
+                data: bits(0),
+                shift_in: false,
+            }
+        }
+
+        fn hold() -> Self {
+            LfsrInput {
+                enable: false,
+                load: false,
+                data: bits(0),
+                shift_in: false,
+            }
+        }
+    }
+
+    /// Load a one into the register and return the states of the next 15 cycles
+    fn run_maximal_length_4(lfsr: Lfsr<4>) -> Vec<u128> {
+        let input = std::iter::once(LfsrInput::load(1))
+            .chain(std::iter::repeat(LfsrInput::clock()).take(15));
+        simulate(lfsr, input)
+            .skip(1)
+            .map(|output| output.state.0)
+            .collect_vec()
+    }
+
+    #[test]
+    fn test_fibonacci_lfsr() {
+        // x^4 + x^3 + 1
+        let lfsr = Lfsr::<4>::fibonacci(0b1100);
+        note_init_db();
+        let states = run_maximal_length_4(lfsr);
+        let mut vcd_file =
+            std::fs::File::create(std::env::temp_dir().join("lfsr_fibonacci.vcd")).unwrap();
+        note_take().unwrap().dump_vcd(&[], &mut vcd_file).unwrap();
+
+        assert_eq!(states[..4], [0b0010, 0b0100, 0b1001, 0b0011]);
+        // A maximal length LFSR visits every non-zero state once
+        assert_eq!(states.iter().unique().count(), 15);
+        assert_eq!(states[14], 1);
+    }
+
+    #[test]
+    fn test_galois_lfsr() {
+        // x^4 + x^3 + 1
+        let lfsr = Lfsr::<4>::galois(0b1001);
+        let states = run_maximal_length_4(lfsr);
+
+        assert_eq!(states[..4], [0b0010, 0b0100, 0b1000, 0b1001]);
+        assert_eq!(states.iter().unique().count(), 15);
+        assert_eq!(states[14], 1);
+    }
+
+    #[test]
+    fn test_lfsr_clock_enable() {
+        let lfsr = Lfsr::<4>::fibonacci(0b1100);
+        let input = [
+            LfsrInput::load(0b1011),
+            LfsrInput::hold(),
+            LfsrInput::clock(),
+            LfsrInput::hold(),
+        ];
+        let outputs = simulate(lfsr, input.into_iter()).collect_vec();
+
+        assert_eq!(outputs[0].state, bits(0b1011));
+        assert_eq!(outputs[1].state, bits(0b1011));
+        assert_eq!(outputs[2].state, bits(0b0111));
+        assert_eq!(outputs[2].bit, false);
+        assert_eq!(outputs[3].state, bits(0b0111));
+    }
+}
@@ -1,15 +1,32 @@
+mod a51;
+mod a51_uart;
 mod adder;
+mod bit_packer;
+mod bit_unpacker;
+mod buffered_uart;
 mod chasing_lights;
+mod cli;
 mod clock_thing;
+mod fifo;
 mod inverter;
 mod jkff;
+mod lfsr;
 mod oneshot_sim;
+mod protocol;
 mod rhdl_blinker_test;
+mod serial_harness;
 mod shift_register;
 mod start_pulse;
 mod sum_accumulator;
+mod synchronizer;
 mod uart;
 
 fn main() {
-    println!("Hello, world!");
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Err(error) = cli::run(&args) {
+        eprintln!("Error: {}", error);
+        eprintln!();
+        eprintln!("{}", cli::USAGE);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,343 @@
+//! Framing protocol for talking to the A5/1 encryptor over the UART.
+//!
+//! Every command is sent as `[opcode, length, payload..., checksum]`. `length` is the number of
+//! payload bytes and `checksum` is the XOR of the opcode, the length and all payload bytes.
+//! Multi-byte values are sent most significant byte first.
+//!
+//! | Command         | Payload                         | Response                    |
+//! |-----------------|---------------------------------|-----------------------------|
+//! | `SET_KEY`       | 8 key bytes                     | Status byte                 |
+//! | `SET_FRAME`     | 3 frame number bytes            | Status byte after key setup |
+//! | `ENCRYPT`       | up to 255 plaintext bytes       | One ciphertext byte each    |
+//! | `GET_KEYSTREAM` | Number of keystream bytes       | The keystream bytes         |
+//! | `STATUS`        | Nothing                         | Status byte, dropped count  |
+//!
+//! A command with a wrong checksum, an unknown opcode or an unexpected length is not executed and
+//! answered with a status byte instead. The only exception is `ENCRYPT`, whose data is encrypted
+//! while it is received. Wait for the response before sending the next command.
+//!
+//! The response to `STATUS` is the status byte followed by the 16 bit number of received bytes
+//! the device dropped since the last reset, see [Status].
+
+pub mod command_decoder;
+
+use std::fmt;
+
+pub const SET_KEY: u8 = 0x01;
+pub const SET_FRAME: u8 = 0x02;
+pub const ENCRYPT: u8 = 0x03;
+pub const GET_KEYSTREAM: u8 = 0x04;
+pub const STATUS: u8 = 0x05;
+
+/// Maximum number of payload bytes in a single command
+pub const MAX_PAYLOAD_LENGTH: usize = 255;
+
+/// Calculate the checksum over the opcode, length and payload of a command
+pub fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |checksum, byte| checksum ^ byte)
+}
+
+/// A command sent from the host to the A5/1 encryptor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Set the 64 bit key. Bit 0 is clocked in first.
+    SetKey(u64),
+    /// Set the 22 bit frame number and run the key setup
+    SetFrame(u32),
+    /// Encrypt the given bytes
+    Encrypt(Vec<u8>),
+    /// Get the given number of keystream bytes
+    GetKeystream(u8),
+    /// Get the status byte and the dropped byte count
+    Status,
+}
+
+impl Command {
+    fn opcode(&self) -> u8 {
+        match self {
+            Command::SetKey(_) => SET_KEY,
+            Command::SetFrame(_) => SET_FRAME,
+            Command::Encrypt(_) => ENCRYPT,
+            Command::GetKeystream(_) => GET_KEYSTREAM,
+            Command::Status => STATUS,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            Command::SetKey(key) => key.to_be_bytes().to_vec(),
+            Command::SetFrame(frame) => (frame & 0x3FFFFF).to_be_bytes()[1..].to_vec(),
+            Command::Encrypt(data) => data.clone(),
+            Command::GetKeystream(count) => vec![*count],
+            Command::Status => Vec::new(),
+        }
+    }
+
+    /// Encode the command into the bytes that are sent to the device.
+    ///
+    /// Panics if the payload is longer than [MAX_PAYLOAD_LENGTH].
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = self.payload();
+        assert!(
+            payload.len() <= MAX_PAYLOAD_LENGTH,
+            "Payload of {} bytes does not fit into a single command",
+            payload.len()
+        );
+        let mut bytes = vec![self.opcode(), payload.len() as u8];
+        bytes.extend(payload);
+        bytes.push(checksum(&bytes));
+        bytes
+    }
+
+    /// Decode the first command in `bytes`.
+    ///
+    /// Returns the command and the number of bytes it occupied.
+    pub fn decode(bytes: &[u8]) -> Result<(Command, usize), ProtocolError> {
+        let [opcode, length, ..] = *bytes else {
+            return Err(ProtocolError::Incomplete);
+        };
+        let end = 2 + length as usize;
+        let Some(&received_checksum) = bytes.get(end) else {
+            return Err(ProtocolError::Incomplete);
+        };
+        let expected_checksum = checksum(&bytes[..end]);
+        if received_checksum != expected_checksum {
+            return Err(ProtocolError::Checksum {
+                expected: expected_checksum,
+                received: received_checksum,
+            });
+        }
+
+        let payload = &bytes[2..end];
+        let command = match (opcode, payload.len()) {
+            (SET_KEY, 8) => Command::SetKey(u64::from_be_bytes(payload.try_into().unwrap())),
+            (SET_FRAME, 3) => Command::SetFrame(
+                u32::from_be_bytes([0, payload[0], payload[1], payload[2]]) & 0x3FFFFF,
+            ),
+            (ENCRYPT, _) => Command::Encrypt(payload.to_vec()),
+            (GET_KEYSTREAM, 1) => Command::GetKeystream(payload[0]),
+            (STATUS, 0) => Command::Status,
+            (SET_KEY | SET_FRAME | GET_KEYSTREAM | STATUS, _) => {
+                return Err(ProtocolError::Length { opcode, length })
+            }
+            _ => return Err(ProtocolError::UnknownOpcode(opcode)),
+        };
+        Ok((command, end + 1))
+    }
+
+    /// Number of bytes the device sends back in response to this command
+    pub fn response_length(&self) -> usize {
+        match self {
+            Command::Encrypt(data) => data.len(),
+            Command::GetKeystream(count) => *count as usize,
+            Command::Status => 3,
+            _ => 1,
+        }
+    }
+}
+
+/// Errors when decoding a command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The command is not complete yet
+    Incomplete,
+    /// The checksum does not match
+    Checksum { expected: u8, received: u8 },
+    /// The opcode is not known
+    UnknownOpcode(u8),
+    /// The length does not match the opcode
+    Length { opcode: u8, length: u8 },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Incomplete => write!(f, "Command is incomplete"),
+            ProtocolError::Checksum { expected, received } => write!(
+                f,
+                "Checksum mismatch: expected {:#04x}, received {:#04x}",
+                expected, received
+            ),
+            ProtocolError::UnknownOpcode(opcode) => write!(f, "Unknown opcode {:#04x}", opcode),
+            ProtocolError::Length { opcode, length } => {
+                write!(f, "Invalid length {} for opcode {:#04x}", length, opcode)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// The status sent by the device
+///
+/// Most commands are answered with the status byte only. `STATUS` also sends `dropped`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Status {
+    /// A key was set
+    pub key_set: bool,
+    /// The key setup is done and keystream can be generated
+    pub ready: bool,
+    /// A command with a wrong checksum was received since the last `STATUS` command
+    pub checksum_error: bool,
+    /// A command with an unknown opcode or length was received since the last `STATUS` command
+    pub unknown_command: bool,
+    /// A received byte was dropped since the last `STATUS` command
+    pub overrun: bool,
+    /// Number of received bytes that were dropped since the last reset
+    ///
+    /// Saturates at 0xFFFF. Not part of the status byte.
+    pub dropped: u16,
+}
+
+impl Status {
+    pub const KEY_SET: u8 = 1 << 0;
+    pub const READY: u8 = 1 << 1;
+    pub const CHECKSUM_ERROR: u8 = 1 << 2;
+    pub const UNKNOWN_COMMAND: u8 = 1 << 3;
+    pub const OVERRUN: u8 = 1 << 4;
+
+    /// Decode a status byte
+    ///
+    /// `dropped` is set to zero.
+    pub fn decode(byte: u8) -> Self {
+        Status {
+            key_set: byte & Self::KEY_SET != 0,
+            ready: byte & Self::READY != 0,
+            checksum_error: byte & Self::CHECKSUM_ERROR != 0,
+            unknown_command: byte & Self::UNKNOWN_COMMAND != 0,
+            overrun: byte & Self::OVERRUN != 0,
+            dropped: 0,
+        }
+    }
+
+    /// Decode the response to a `STATUS` command
+    ///
+    /// Returns `None` if `bytes` does not have the length of the response.
+    pub fn decode_response(bytes: &[u8]) -> Option<Self> {
+        let [byte, high, low] = *bytes else {
+            return None;
+        };
+        Some(Status {
+            dropped: u16::from_be_bytes([high, low]),
+            ..Status::decode(byte)
+        })
+    }
+
+    /// Encode the status into a status byte
+    pub fn encode(&self) -> u8 {
+        (self.key_set as u8 * Self::KEY_SET)
+            | (self.ready as u8 * Self::READY)
+            | (self.checksum_error as u8 * Self::CHECKSUM_ERROR)
+            | (self.unknown_command as u8 * Self::UNKNOWN_COMMAND)
+            | (self.overrun as u8 * Self::OVERRUN)
+    }
+
+    /// Encode the status into the response to a `STATUS` command
+    pub fn encode_response(&self) -> [u8; 3] {
+        let [high, low] = self.dropped.to_be_bytes();
+        [self.encode(), high, low]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Command, ProtocolError, Status};
+
+    #[test]
+    fn test_encode_set_key() {
+        assert_eq!(
+            Command::SetKey(0xEFCDAB8967452312).encode(),
+            [0x01, 0x08, 0xEF, 0xCD, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x12, 0x1A]
+        );
+    }
+
+    #[test]
+    fn test_encode_set_frame() {
+        assert_eq!(
+            Command::SetFrame(0x134).encode(),
+            [0x02, 0x03, 0x00, 0x01, 0x34, 0x34]
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let commands = [
+            Command::SetKey(0x0123456789ABCDEF),
+            Command::SetFrame(0x3FFFFF),
+            Command::Encrypt(b"Hello A5/1".to_vec()),
+            Command::Encrypt(Vec::new()),
+            Command::GetKeystream(15),
+            Command::Status,
+        ];
+        let bytes = commands
+            .iter()
+            .flat_map(Command::encode)
+            .collect::<Vec<_>>();
+
+        let mut decoded = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let (command, length) = Command::decode(&bytes[offset..]).unwrap();
+            decoded.push(command);
+            offset += length;
+        }
+        assert_eq!(decoded, commands);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut bytes = Command::GetKeystream(4).encode();
+        assert_eq!(Command::decode(&bytes[..3]), Err(ProtocolError::Incomplete));
+
+        bytes[2] = 5;
+        assert_eq!(
+            Command::decode(&bytes),
+            Err(ProtocolError::Checksum {
+                expected: 0x04 ^ 0x01 ^ 0x05,
+                received: 0x04 ^ 0x01 ^ 0x04
+            })
+        );
+
+        assert_eq!(
+            Command::decode(&[0x42, 0x00, 0x42]),
+            Err(ProtocolError::UnknownOpcode(0x42))
+        );
+        assert_eq!(
+            Command::decode(&[0x05, 0x01, 0x00, 0x04]),
+            Err(ProtocolError::Length {
+                opcode: 0x05,
+                length: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_status_byte() {
+        let status = Status {
+            key_set: true,
+            ready: false,
+            checksum_error: true,
+            unknown_command: false,
+            overrun: true,
+            dropped: 0,
+        };
+        assert_eq!(status.encode(), 0b10101);
+        assert_eq!(Status::decode(0b10101), status);
+    }
+
+    #[test]
+    fn test_status_response() {
+        let status = Status {
+            ready: true,
+            overrun: true,
+            dropped: 0x1234,
+            ..Default::default()
+        };
+        assert_eq!(status.encode_response(), [0b10010, 0x12, 0x34]);
+        assert_eq!(
+            Status::decode_response(&[0b10010, 0x12, 0x34]),
+            Some(status)
+        );
+        assert_eq!(Status::decode_response(&[0b10010]), None);
+    }
+}
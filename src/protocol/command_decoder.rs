@@ -0,0 +1,352 @@
+use rhdl::{bits::bits, kernel, Bits, Digital};
+use rhdl_core::{note, Synchronous};
+use rhdl_std::{get_bit, set_bit};
+
+/// Decodes the commands of the A5/1 protocol from the bytes received by a UartReceiver.
+///
+/// See the [protocol module](crate::protocol) for a description of the protocol.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct CommandDecoder {
+    // TODO: Crashes when generating verilog and there are no fields in the struct
+    /// Ignore the checksum byte. Useful for typing commands into a terminal.
+    ignore_checksum: bool,
+}
+
+impl CommandDecoder {
+    /// Create a new CommandDecoder that checks the checksum of every command.
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        CommandDecoder {
+            ignore_checksum: false,
+        }
+    }
+}
+
+/// The opcode of the command that is currently decoded
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub enum Opcode {
+    /// The opcode is unknown or the length does not match the opcode
+    #[default]
+    Unknown,
+    SetKey,
+    SetFrame,
+    Encrypt,
+    GetKeystream,
+    Status,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct CommandDecoderInput {
+    /// Reset signal. Pull high to drop the partially received command.
+    pub reset: bool,
+    /// The received byte
+    pub data: Bits<8>,
+    /// Set to high when data was received
+    pub valid: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct CommandDecoderOutput {
+    /// The opcode of the current command
+    pub opcode: Opcode,
+    /// The current payload byte
+    pub data: Bits<8>,
+    /// Set to high for one cycle when a payload byte was received
+    pub data_valid: bool,
+    /// Set to high for one cycle when the checksum byte was received
+    pub done: bool,
+    /// Set to high together with `done` when the checksum did not match
+    pub checksum_error: bool,
+    /// The last 8 payload bytes as key
+    pub key: Bits<64>,
+    /// The last 3 payload bytes as frame number
+    pub frame: Bits<22>,
+    /// The last payload byte as count
+    pub count: Bits<8>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub enum CommandDecoderStateEnum {
+    /// Waiting for the opcode
+    #[default]
+    Opcode,
+    /// Waiting for the length
+    Length,
+    /// Receiving the payload
+    Payload,
+    /// Waiting for the checksum
+    Checksum,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct CommandDecoderState {
+    /// The opcode of the current command
+    opcode: Opcode,
+    /// Number of payload bytes that are still missing
+    remaining: Bits<8>,
+    /// XOR of all bytes received so far
+    checksum: Bits<8>,
+    /// The payload shifted in as key
+    key: Bits<64>,
+    /// The payload shifted in as frame number
+    frame: Bits<22>,
+    /// The last payload byte
+    count: Bits<8>,
+    /// The current state of the decoder
+    state: CommandDecoderStateEnum,
+}
+
+impl CommandDecoderState {
+    pub const fn default() -> Self {
+        CommandDecoderState {
+            opcode: Opcode::Unknown,
+            remaining: bits::<8>(0),
+            checksum: bits::<8>(0),
+            key: bits::<64>(0),
+            frame: bits::<22>(0),
+            count: bits::<8>(0),
+            state: CommandDecoderStateEnum::Opcode,
+        }
+    }
+}
+
+impl Synchronous for CommandDecoder {
+    type Input = CommandDecoderInput;
+    type Output = CommandDecoderOutput;
+    type State = CommandDecoderState;
+    type Update = command_decoder_update;
+
+    const INITIAL_STATE: Self::State = CommandDecoderState::default();
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) =
+        command_decoder_update;
+}
+
+/// Shift `value` left by 8 bits and put `byte` into the lower 8 bits.
+#[kernel]
+pub fn shift_in_byte<const N: usize>(value: Bits<N>, byte: Bits<8>) -> Bits<N> {
+    let mut shifted = value << bits::<N>(8);
+    shifted = set_bit::<N>(shifted, 0, get_bit::<8>(byte, 0));
+    shifted = set_bit::<N>(shifted, 1, get_bit::<8>(byte, 1));
+    shifted = set_bit::<N>(shifted, 2, get_bit::<8>(byte, 2));
+    shifted = set_bit::<N>(shifted, 3, get_bit::<8>(byte, 3));
+    shifted = set_bit::<N>(shifted, 4, get_bit::<8>(byte, 4));
+    shifted = set_bit::<N>(shifted, 5, get_bit::<8>(byte, 5));
+    shifted = set_bit::<N>(shifted, 6, get_bit::<8>(byte, 6));
+    shifted = set_bit::<N>(shifted, 7, get_bit::<8>(byte, 7));
+    shifted
+}
+
+#[kernel]
+pub fn command_decoder_update(
+    params: CommandDecoder,
+    state: CommandDecoderState,
+    input: CommandDecoderInput,
+) -> (CommandDecoderState, CommandDecoderOutput) {
+    note("input", input);
+
+    let checksum = state.checksum ^ input.data;
+
+    let next_state: CommandDecoderState = if input.reset {
+        CommandDecoderState {
+            opcode: Opcode::Unknown,
+            remaining: bits::<8>(0),
+            checksum: bits::<8>(0),
+            key: state.key,
+            frame: state.frame,
+            count: state.count,
+            state: CommandDecoderStateEnum::Opcode,
+        }
+    } else if !input.valid {
+        state
+    } else {
+        match state.state {
+            CommandDecoderStateEnum::Opcode => CommandDecoderState {
+                opcode: match input.data {
+                    Bits::<8>(0x01) => Opcode::SetKey,
+                    Bits::<8>(0x02) => Opcode::SetFrame,
+                    Bits::<8>(0x03) => Opcode::Encrypt,
+                    Bits::<8>(0x04) => Opcode::GetKeystream,
+                    Bits::<8>(0x05) => Opcode::Status,
+                    _ => Opcode::Unknown,
+                },
+                remaining: bits::<8>(0),
+                checksum: input.data,
+                key: state.key,
+                frame: state.frame,
+                count: state.count,
+                state: CommandDecoderStateEnum::Length,
+            },
+            CommandDecoderStateEnum::Length => {
+                let length_valid = match state.opcode {
+                    Opcode::Unknown => true,
+                    Opcode::SetKey => input.data == 8,
+                    Opcode::SetFrame => input.data == 3,
+                    Opcode::Encrypt => true,
+                    Opcode::GetKeystream => input.data == 1,
+                    Opcode::Status => input.data == 0,
+                };
+                CommandDecoderState {
+                    opcode: if length_valid {
+                        state.opcode
+                    } else {
+                        Opcode::Unknown
+                    },
+                    remaining: input.data,
+                    checksum,
+                    key: state.key,
+                    frame: state.frame,
+                    count: state.count,
+                    state: if input.data == 0 {
+                        CommandDecoderStateEnum::Checksum
+                    } else {
+                        CommandDecoderStateEnum::Payload
+                    },
+                }
+            }
+            CommandDecoderStateEnum::Payload => CommandDecoderState {
+                opcode: state.opcode,
+                remaining: state.remaining - 1,
+                checksum,
+                key: shift_in_byte::<64>(state.key, input.data),
+                frame: shift_in_byte::<22>(state.frame, input.data),
+                count: input.data,
+                state: if state.remaining == 1 {
+                    CommandDecoderStateEnum::Checksum
+                } else {
+                    CommandDecoderStateEnum::Payload
+                },
+            },
+            CommandDecoderStateEnum::Checksum => CommandDecoderState {
+                opcode: state.opcode,
+                remaining: bits::<8>(0),
+                checksum: bits::<8>(0),
+                key: state.key,
+                frame: state.frame,
+                count: state.count,
+                state: CommandDecoderStateEnum::Opcode,
+            },
+        }
+    };
+
+    let valid = input.valid & !input.reset;
+    let done = valid & (state.state == CommandDecoderStateEnum::Checksum);
+    let output = CommandDecoderOutput {
+        opcode: next_state.opcode,
+        data: input.data,
+        data_valid: valid & (state.state == CommandDecoderStateEnum::Payload),
+        done,
+        checksum_error: done & !params.ignore_checksum & (state.checksum != input.data),
+        key: next_state.key,
+        frame: next_state.frame,
+        count: next_state.count,
+    };
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CommandDecoder, CommandDecoderInput, CommandDecoderOutput, Opcode};
+    use crate::protocol::Command;
+    use itertools::Itertools;
+    use rhdl::{bits::bits, synchronous::simulate};
+    use rhdl_core::{note_init_db, note_take};
+
+    /// Feed the bytes into the decoder with an idle cycle after each byte
+    fn decode(bytes: &[u8]) -> Vec<CommandDecoderOutput> {
+        let input = bytes.iter().flat_map(|byte| {
+            [
+                CommandDecoderInput {
+                    reset: false,
+                    data: bits(*byte as u128),
+                    valid: true,
+                },
+                CommandDecoderInput::default(),
+            ]
+        });
+        simulate(CommandDecoder::new(), input).collect_vec()
+    }
+
+    fn done(outputs: &[CommandDecoderOutput]) -> Vec<CommandDecoderOutput> {
+        outputs
+            .iter()
+            .filter(|output| output.done)
+            .copied()
+            .collect_vec()
+    }
+
+    #[test]
+    fn test_decode_commands() {
+        let bytes = [
+            Command::SetKey(0xEFCDAB8967452312),
+            Command::SetFrame(0x134),
+            Command::GetKeystream(42),
+            Command::Status,
+        ]
+        .iter()
+        .flat_map(Command::encode)
+        .collect_vec();
+
+        note_init_db();
+        let outputs = decode(&bytes);
+        let mut vcd_file =
+            std::fs::File::create(std::env::temp_dir().join("command_decoder.vcd")).unwrap();
+        note_take().unwrap().dump_vcd(&[], &mut vcd_file).unwrap();
+
+        let done = done(&outputs);
+        assert_eq!(done.len(), 4);
+        assert!(done.iter().all(|output| !output.checksum_error));
+        assert_eq!(done[0].opcode, Opcode::SetKey);
+        assert_eq!(done[0].key, bits(0xEFCDAB8967452312));
+        assert_eq!(done[1].opcode, Opcode::SetFrame);
+        assert_eq!(done[1].frame, bits(0x134));
+        assert_eq!(done[2].opcode, Opcode::GetKeystream);
+        assert_eq!(done[2].count, bits(42));
+        assert_eq!(done[3].opcode, Opcode::Status);
+    }
+
+    #[test]
+    fn test_decode_encrypt_payload() {
+        let outputs = decode(&Command::Encrypt(b"A5/1".to_vec()).encode());
+
+        let payload = outputs
+            .iter()
+            .filter(|output| output.data_valid)
+            .map(|output| {
+                assert_eq!(output.opcode, Opcode::Encrypt);
+                output.data.0 as u8
+            })
+            .collect_vec();
+        assert_eq!(payload, b"A5/1");
+        assert_eq!(done(&outputs).len(), 1);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut bad_checksum = Command::SetKey(0x0123456789ABCDEF).encode();
+        *bad_checksum.last_mut().unwrap() ^= 0xFF;
+        let bad_length = [0x05, 0x01, 0x00, 0x04];
+        let unknown = [0x42, 0x02, 0x00, 0x00, 0x40];
+        let bytes = [
+            bad_checksum.as_slice(),
+            &bad_length,
+            &unknown,
+            &Command::Status.encode(),
+        ]
+        .concat();
+
+        let done = done(&decode(&bytes));
+        assert_eq!(done.len(), 4);
+        assert_eq!(done[0].opcode, Opcode::SetKey);
+        assert!(done[0].checksum_error);
+        assert_eq!(done[1].opcode, Opcode::Unknown);
+        assert!(!done[1].checksum_error);
+        assert_eq!(done[2].opcode, Opcode::Unknown);
+        assert!(!done[2].checksum_error);
+        assert_eq!(done[3].opcode, Opcode::Status);
+        assert!(!done[3].checksum_error);
+    }
+}
@@ -0,0 +1,156 @@
+//! Simulated serial line for testing designs that are attached to a UART.
+//!
+//! The harness serializes bytes into 8N1 line samples on RX, runs the design and decodes the
+//! samples on TX back into bytes.
+
+use rhdl::synchronous::{simulate_first_cycle, simulate_one_cycle};
+use rhdl_core::{ClockDetails, Synchronous};
+
+/// A design with a serial RX input and a serial TX output
+pub trait SerialDesign: Synchronous {
+    /// Create the input for the next cycle from the RX line and the output of the last cycle
+    fn serial_input(rx: bool, previous_output: &Self::Output) -> Self::Input;
+    /// Get the TX line from the output
+    fn serial_output(output: &Self::Output) -> bool;
+}
+
+/// A byte that was decoded from the TX line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Received {
+    /// The received byte
+    pub byte: u8,
+    /// The cycle in which the start bit began
+    pub cycle: usize,
+}
+
+/// Serialize bytes into 8N1 line samples with `clocks_per_bit` samples per bit.
+pub fn serialize(bytes: &[u8], clocks_per_bit: usize) -> Vec<bool> {
+    bytes
+        .iter()
+        .flat_map(|byte| {
+            std::iter::once(false)
+                .chain((0..8).map(move |i| (byte >> i) & 1 == 1))
+                .chain(std::iter::once(true))
+        })
+        .flat_map(|bit| std::iter::repeat(bit).take(clocks_per_bit))
+        .collect()
+}
+
+/// Decode 8N1 line samples with `clocks_per_bit` samples per bit into bytes.
+///
+/// Every bit is sampled in its middle. Frames that are cut off at the end are ignored.
+pub fn deserialize(line: &[bool], clocks_per_bit: usize) -> Vec<Received> {
+    let mut received = Vec::new();
+    let mut cycle = 0;
+    while cycle + 10 * clocks_per_bit <= line.len() {
+        if line[cycle] {
+            cycle += 1;
+            continue;
+        }
+        let byte = (0..8).fold(0u8, |byte, i| {
+            byte | ((line[cycle + (i + 1) * clocks_per_bit + clocks_per_bit / 2] as u8) << i)
+        });
+        received.push(Received { byte, cycle });
+        cycle += 10 * clocks_per_bit;
+    }
+    received
+}
+
+/// Drives a [SerialDesign] with messages and collects the bytes it sends back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SerialHarness {
+    /// Clock cycles per bit on both lines
+    clocks_per_bit: usize,
+    /// Idle cycles before every message and after the last one
+    gap: usize,
+}
+
+impl SerialHarness {
+    /// Create a new harness with one bit every `clocks_per_bit` cycles.
+    ///
+    /// The messages are separated by 20 idle bits.
+    pub fn new(clocks_per_bit: usize) -> Self {
+        SerialHarness {
+            clocks_per_bit,
+            gap: 20 * clocks_per_bit,
+        }
+    }
+
+    /// Separate the messages by `gap` idle cycles instead.
+    ///
+    /// Use this to give the design enough time to respond to a message.
+    pub fn with_gap(self, gap: usize) -> Self {
+        SerialHarness { gap, ..self }
+    }
+
+    /// The RX line samples for the messages
+    pub fn line(&self, messages: &[&[u8]]) -> Vec<bool> {
+        messages
+            .iter()
+            .flat_map(|message| {
+                std::iter::repeat(true)
+                    .take(self.gap)
+                    .chain(serialize(message, self.clocks_per_bit))
+            })
+            .chain(std::iter::repeat(true).take(self.gap))
+            .collect()
+    }
+
+    /// Send the messages to the design and return the bytes it sent with their cycle numbers.
+    pub fn run<T: SerialDesign>(&self, design: T, messages: &[&[u8]]) -> Vec<Received>
+    where
+        T::Output: Default,
+    {
+        let clock = ClockDetails::new("clock", 1000 * 1000, 0, false);
+        let mut tx = Vec::new();
+
+        let (mut state, mut output, mut time) =
+            simulate_first_cycle(design, T::serial_input(true, &Default::default()), &clock);
+        tx.push(T::serial_output(&output));
+        for rx in self.line(messages) {
+            (state, output, time) =
+                simulate_one_cycle(design, T::serial_input(rx, &output), state, time, &clock);
+            tx.push(T::serial_output(&output));
+        }
+
+        deserialize(&tx, self.clocks_per_bit)
+    }
+
+    /// Send the messages to the design and return the bytes it sent.
+    pub fn transfer<T: SerialDesign>(&self, design: T, messages: &[&[u8]]) -> Vec<u8>
+    where
+        T::Output: Default,
+    {
+        self.run(design, messages)
+            .iter()
+            .map(|received| received.byte)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{deserialize, serialize, Received};
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let line = [vec![true; 3], serialize(b"A5/1", 4), vec![true; 5]].concat();
+        let received = deserialize(&line, 4);
+
+        assert_eq!(
+            received[0],
+            Received {
+                byte: b'A',
+                cycle: 3
+            }
+        );
+        assert_eq!(received[1].cycle, 3 + 40);
+        assert_eq!(
+            received
+                .iter()
+                .map(|received| received.byte)
+                .collect::<Vec<_>>(),
+            b"A5/1"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use rhdl::{bits::bits, kernel, Bits, Digital};
+use rhdl_core::{note, Synchronous};
+use rhdl_std::{get_bit, set_bit};
+
+/// A chain of N flip flops to synchronize an asynchronous input to the clock.
+///
+/// Use this for every input that comes from an external pin. Two flops are enough in most cases,
+/// the output is delayed by N clock cycles. All flops start high, which is the idle level of a
+/// UART line.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct Synchronizer<const N: usize> {
+    /// Index of the last flop in the chain
+    // TODO: Figure out how to use N inside a kernel function
+    output_tap: u8,
+}
+
+impl<const N: usize> Synchronizer<N> {
+    /// Create a new synchronizer with N flops.
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Synchronizer {
+            output_tap: (N - 1) as u8,
+        }
+    }
+}
+
+impl<const N: usize> Synchronous for Synchronizer<N> {
+    type Input = bool;
+    type Output = bool;
+    type State = Bits<N>;
+    type Update = synchronizer_update<N>;
+
+    const INITIAL_STATE: Self::State = Bits(u128::MAX >> (128 - N));
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) =
+        synchronizer_update::<N>;
+}
+
+#[kernel]
+pub fn synchronizer_update<const N: usize>(
+    params: Synchronizer<N>,
+    state: Bits<N>,
+    input: bool,
+) -> (Bits<N>, bool) {
+    note("input", input);
+
+    let next_state = set_bit::<N>(state << bits::<N>(1), 0, input);
+    let output = get_bit::<N>(state, params.output_tap);
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Synchronizer;
+    use itertools::Itertools;
+    use rhdl::synchronous::simulate;
+    use rhdl_core::{
+        compile_design, generate_verilog, note_init_db, note_take, DigitalFn, KernelFnKind,
+        Synchronous,
+    };
+
+    #[test]
+    fn test_synchronizer_delays_input() {
+        let input = [false, true, true, false, false, false, true, true];
+
+        note_init_db();
+        let output = simulate(Synchronizer::<2>::new(), input.into_iter()).collect_vec();
+        let mut vcd_file =
+            std::fs::File::create(std::env::temp_dir().join("synchronizer.vcd")).unwrap();
+        note_take().unwrap().dump_vcd(&[], &mut vcd_file).unwrap();
+
+        // The output starts high and follows the input two cycles later
+        assert_eq!(output, [true, true, false, true, true, false, false, false]);
+    }
+
+    #[test]
+    fn test_synchronizer_length() {
+        let input = std::iter::once(false).chain(std::iter::repeat(true).take(5));
+        let output = simulate(Synchronizer::<3>::new(), input).collect_vec();
+        assert_eq!(output, [true, true, true, false, true, true]);
+    }
+
+    #[test]
+    fn test_generate_verilog() {
+        let Some(KernelFnKind::Kernel(kernel)) =
+            <Synchronizer<2> as Synchronous>::Update::kernel_fn()
+        else {
+            panic!("No kernel function found");
+        };
+        let design = &compile_design(kernel).unwrap();
+        let verilog = generate_verilog(design).unwrap();
+        std::fs::write(
+            std::env::temp_dir().join("synchronizer.v"),
+            format!("{}", verilog),
+        )
+        .unwrap();
+    }
+}
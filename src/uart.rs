@@ -1,17 +1,232 @@
+mod baud_generator;
 mod uart_receiver;
 mod uart_sender;
 
-use rhdl::{kernel, Bits, Digital};
+use std::fmt;
+
+use rhdl::{bits::bits, kernel, Bits, Digital};
 use rhdl_core::{note, note_pop_path, note_push_path, Synchronous};
-use uart_receiver::{uart_receiver_update, UartReceiver, UartReceiverInput, UartReceiverState};
+use uart_receiver::{
+    uart_receiver_update, uart_receiver_waiting, UartReceiver, UartReceiverInput, UartReceiverState,
+};
 use uart_sender::{uart_sender_update, UartSender, UartSenderInput, UartSenderState};
 
+use crate::serial_harness::SerialDesign;
+use crate::synchronizer::{synchronizer_update, Synchronizer};
+pub use baud_generator::baud_error;
+
+/// The largest timing error of the bit edges in percent of a bit that `try_new` accepts
+pub const MAX_BAUD_ERROR: f64 = 5.0;
+/// The largest number of clock cycles per bit. Two stop bits still have to fit into 32 bits.
+const MAX_DIVISOR: u128 = u32::MAX as u128 / 2;
+
+/// Parity bit that is sent after the data bits
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub enum UartParity {
+    /// No parity bit
+    #[default]
+    None,
+    /// The number of ones in the data and parity bits is even
+    Even,
+    /// The number of ones in the data and parity bits is odd
+    Odd,
+    /// The parity bit is always high
+    Mark,
+    /// The parity bit is always low
+    Space,
+}
+
+/// Number of stop bits at the end of a frame
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub enum UartStopBits {
+    #[default]
+    One,
+    OneAndHalf,
+    Two,
+}
+
+/// Frame format of a UART
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct UartFormat {
+    /// Number of data bits from 5 to 9
+    ///
+    /// The ninth data bit is transferred separately from the data byte.
+    pub data_bits: u8,
+    /// The parity bit
+    pub parity: UartParity,
+    /// The number of stop bits
+    ///
+    /// The receiver only checks the first stop bit.
+    pub stop_bits: UartStopBits,
+}
+
+impl UartFormat {
+    /// Create a new frame format.
+    ///
+    /// Panics if `data_bits` is not between 5 and 9.
+    pub fn new(data_bits: u8, parity: UartParity, stop_bits: UartStopBits) -> Self {
+        assert!(
+            (5..=9).contains(&data_bits),
+            "A UART frame has between 5 and 9 data bits, not {}",
+            data_bits
+        );
+        UartFormat {
+            data_bits,
+            parity,
+            stop_bits,
+        }
+    }
+}
+
+impl Default for UartFormat {
+    /// 8 data bits, no parity and one stop bit
+    fn default() -> Self {
+        UartFormat::new(8, UartParity::None, UartStopBits::One)
+    }
+}
+
+/// Errors for clock speeds and bit rates that a Uart can not be built for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UartConfigError {
+    /// The bit rate is zero
+    ZeroBitRate,
+    /// There are less than 2 clock cycles per bit
+    DivisorTooSmall { clock_speed: u128, bit_rate: u128 },
+    /// The clock cycles per bit do not fit into the counters
+    DivisorTooLarge { clock_speed: u128, bit_rate: u128 },
+    /// The bit edges are too far from their ideal position
+    BaudErrorTooLarge {
+        clock_speed: u128,
+        bit_rate: u128,
+        error: f64,
+    },
+}
+
+impl fmt::Display for UartConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UartConfigError::ZeroBitRate => write!(f, "The bit rate must not be zero"),
+            UartConfigError::DivisorTooSmall {
+                clock_speed,
+                bit_rate,
+            } => write!(
+                f,
+                "{} baud needs at least 2 clock cycles per bit, the clock speed is {} Hz",
+                bit_rate, clock_speed
+            ),
+            UartConfigError::DivisorTooLarge {
+                clock_speed,
+                bit_rate,
+            } => write!(
+                f,
+                "{} baud needs at most {} clock cycles per bit, the clock speed is {} Hz",
+                bit_rate, MAX_DIVISOR, clock_speed
+            ),
+            UartConfigError::BaudErrorTooLarge {
+                clock_speed,
+                bit_rate,
+                error,
+            } => write!(
+                f,
+                "{} baud is off by {:.2}% at a clock speed of {} Hz, at most {}% are allowed",
+                bit_rate, error, clock_speed, MAX_BAUD_ERROR
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UartConfigError {}
+
+/// Check that the clock cycles per bit fit into the counters of the sender and the receiver.
+///
+/// This is all the infallible constructors check. [check_bit_rate] also rejects bit rates that
+/// can not be generated precisely enough.
+fn check_divisor(clock_speed: u128, bit_rate: u128) -> Result<(), UartConfigError> {
+    if bit_rate == 0 {
+        return Err(UartConfigError::ZeroBitRate);
+    }
+    let divisor = clock_speed / bit_rate;
+    if divisor == 0 {
+        return Err(UartConfigError::DivisorTooSmall {
+            clock_speed,
+            bit_rate,
+        });
+    }
+    if divisor > MAX_DIVISOR {
+        return Err(UartConfigError::DivisorTooLarge {
+            clock_speed,
+            bit_rate,
+        });
+    }
+    Ok(())
+}
+
+/// Check that a Uart can be built for the clock speed and bit rate.
+pub fn check_bit_rate(clock_speed: u128, bit_rate: u128) -> Result<(), UartConfigError> {
+    check_divisor(clock_speed, bit_rate)?;
+    if clock_speed / bit_rate < 2 {
+        return Err(UartConfigError::DivisorTooSmall {
+            clock_speed,
+            bit_rate,
+        });
+    }
+    let error = baud_error(clock_speed, bit_rate);
+    if error > MAX_BAUD_ERROR {
+        return Err(UartConfigError::BaudErrorTooLarge {
+            clock_speed,
+            bit_rate,
+            error,
+        });
+    }
+    Ok(())
+}
+
+/// Calculate the parity bit for data with the given number of ones.
+///
+/// `odd_ones` is the XOR of all data bits.
+#[kernel]
+pub fn parity_bit(parity: UartParity, odd_ones: bool) -> bool {
+    match parity {
+        UartParity::None => false,
+        UartParity::Even => odd_ones,
+        UartParity::Odd => !odd_ones,
+        UartParity::Mark => true,
+        UartParity::Space => false,
+    }
+}
+
+/// Calculate the length of the stop bits in clock cycles.
+#[kernel]
+pub fn stop_length(stop_bits: UartStopBits, bitlength: Bits<32>) -> Bits<32> {
+    match stop_bits {
+        UartStopBits::One => bitlength,
+        UartStopBits::OneAndHalf => bitlength + (bitlength >> bits::<32>(1)),
+        UartStopBits::Two => bitlength + bitlength,
+    }
+}
+
+/// Check whether a data byte has to be escaped with software flow control.
+///
+/// These are XON (0x11), XOFF (0x13) and the escape character (0x7D) itself.
+#[kernel]
+pub fn is_control_character(data: Bits<8>) -> bool {
+    (data == bits::<8>(0x11)) | (data == bits::<8>(0x13)) | (data == bits::<8>(0x7D))
+}
+
 /// Combines a UartReceiver and a UartSender into a single Uart component.
 ///
 /// Not really sure, whether this is a good idea. But the example in the exercises also did it this way.
 /// At least it is an exercise for combining multiple components into a single one.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
 pub struct Uart {
+    /// Synchronizes the asynchronous RX line to the clock
+    synchronizer: Synchronizer<2>,
+    /// Set to false to feed the RX line directly into the receiver
+    synchronize_rx: bool,
+    /// Set to true to use the RTS and CTS lines
+    flow_control: bool,
+    /// Set to true to use XON and XOFF bytes for flow control
+    software_flow_control: bool,
     receiver: UartReceiver,
     sender: UartSender,
 }
@@ -20,9 +235,78 @@ impl Uart {
     #[allow(dead_code)]
     /// Create a new Uart with a given clock speed and bit rate.
     pub fn new(clock_speed: u128, bit_rate: u128) -> Self {
+        Uart::with_format(clock_speed, bit_rate, UartFormat::default())
+    }
+
+    #[allow(dead_code)]
+    /// Create a new Uart with a given clock speed, bit rate and frame format.
+    pub fn with_format(clock_speed: u128, bit_rate: u128, format: UartFormat) -> Self {
+        Uart {
+            synchronizer: Synchronizer::new(),
+            synchronize_rx: true,
+            flow_control: false,
+            software_flow_control: false,
+            receiver: UartReceiver::with_format(clock_speed, bit_rate, format),
+            sender: UartSender::with_format(clock_speed, bit_rate, format),
+        }
+    }
+
+    /// Create a new Uart with a given clock speed and bit rate.
+    ///
+    /// Fails if the bit rate can not be generated from the clock speed, see [check_bit_rate].
+    pub fn try_new(clock_speed: u128, bit_rate: u128) -> Result<Self, UartConfigError> {
+        Uart::try_with_format(clock_speed, bit_rate, UartFormat::default())
+    }
+
+    /// Create a new Uart with a given clock speed, bit rate and frame format.
+    ///
+    /// Fails if the bit rate can not be generated from the clock speed, see [check_bit_rate].
+    #[allow(dead_code)]
+    pub fn try_with_format(
+        clock_speed: u128,
+        bit_rate: u128,
+        format: UartFormat,
+    ) -> Result<Self, UartConfigError> {
+        Ok(Uart {
+            receiver: UartReceiver::try_with_format(clock_speed, bit_rate, format)?,
+            sender: UartSender::try_with_format(clock_speed, bit_rate, format)?,
+            ..Uart::with_format(clock_speed, bit_rate, format)
+        })
+    }
+
+    #[allow(dead_code)]
+    /// Feed the RX line directly into the receiver.
+    ///
+    /// Only use this if RX is already synchronous to the clock, for example in a loopback.
+    pub fn without_synchronizer(self) -> Self {
+        Uart {
+            synchronize_rx: false,
+            ..self
+        }
+    }
+
+    #[allow(dead_code)]
+    /// Enable hardware flow control with the RTS and CTS lines.
+    ///
+    /// Without flow control CTS is ignored and RTS is always high.
+    pub fn with_flow_control(self) -> Self {
         Uart {
-            receiver: UartReceiver::new(clock_speed, bit_rate),
-            sender: UartSender::new(clock_speed, bit_rate),
+            flow_control: true,
+            ..self
+        }
+    }
+
+    #[allow(dead_code)]
+    /// Enable software flow control with XON (0x11) and XOFF (0x13) bytes.
+    ///
+    /// A received XOFF pauses the sender until an XON is received. XOFF is sent while the buffer
+    /// for received bytes is almost full and XON once it is not. Data bytes that are control
+    /// characters are sent as the escape character 0x7D followed by the byte XOR 0x20. Only works
+    /// with 8 data bits.
+    pub fn with_software_flow_control(self) -> Self {
+        Uart {
+            software_flow_control: true,
+            ..self
         }
     }
 }
@@ -30,38 +314,105 @@ impl Uart {
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
 pub struct UartInput {
     /// Reset signal. Pull high to reset the state machine.
-    reset: bool,
+    pub reset: bool,
     /// Data input line
-    rx: bool,
+    pub rx: bool,
     /// Data to transmit
-    data: Bits<8>,
-    /// Pulse high to transmit data
-    start: bool,
+    pub data: Bits<8>,
+    /// Ninth data bit to transmit. Only used with 9 data bits.
+    pub ninth_bit: bool,
+    /// Set to high to transmit `data`
+    ///
+    /// The byte is taken in the cycle where both `valid` and `ready` are high. Keep `data` and
+    /// `valid` until then.
+    pub valid: bool,
+    /// New bit length in clock cycles for both directions
+    ///
+    /// Zero restores the bit length from the parameters.
+    pub divisor: Bits<32>,
+    /// Pulse high to load `divisor`
+    pub set_divisor: bool,
+    /// Clear to send. The sender does not start a new byte while this is low.
+    ///
+    /// Only used with flow control.
+    pub cts: bool,
+    /// Set to high if the received data is taken in this cycle
+    pub rx_ready: bool,
+    /// Set to high to clear `overrun_error`
+    pub clear_overrun: bool,
+    /// Set to high if the buffer for received bytes is almost full.
+    ///
+    /// Drops RTS with flow control and sends XOFF with software flow control.
+    pub rx_almost_full: bool,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
 pub struct UartOutput {
     /// Current output data
-    received_data: Bits<8>,
-    /// Set to true when new data was received transmitted
-    valid: bool,
-    /// Set to true when new data can be transmitted
-    ready: bool,
+    pub received_data: Bits<8>,
+    /// Ninth bit of the current output data. Only used with 9 data bits.
+    pub received_ninth_bit: bool,
+    /// Set to true while received data is waiting to be taken with `rx_ready`
+    pub valid: bool,
+    /// Set to true together with `valid` when the parity bit was wrong
+    pub parity_error: bool,
+    /// Set to true together with `valid` when the stop bit was low
+    pub framing_error: bool,
+    /// Set to true for one cycle when the line was low for a whole frame
+    pub break_detected: bool,
+    /// High when a byte can be accepted this cycle; the byte is taken when `valid` and `ready` are
+    /// both high.
+    pub ready: bool,
     /// Data output line
-    tx: bool,
+    pub tx: bool,
+    /// The current bit length in clock cycles
+    pub divisor: Bits<32>,
+    /// Request to send. Low while no more bytes should be sent to the receiver.
+    pub rts: bool,
+    /// Set to true for one cycle when received data was dropped because it was not taken
+    pub overrun: bool,
+    /// Set to true after received data was dropped until it is cleared with `clear_overrun`
+    pub overrun_error: bool,
+    /// Number of dropped bytes since the last reset. Stops at the maximum.
+    pub dropped: Bits<16>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
 pub struct UartState {
+    synchronizer: Bits<2>,
+    cts_synchronizer: Bits<2>,
     receiver: UartReceiverState,
     sender: UartSenderState,
+    /// Set to high after an XOFF was received
+    paused: bool,
+    /// Set to high after an XOFF was sent
+    xoff_sent: bool,
+    /// Set to high if the last received byte was the escape character
+    escaped: bool,
+    /// Escaped data byte that is sent after the escape character
+    pending: Bits<8>,
+    /// Set to high if `pending` still needs to be sent
+    pending_valid: bool,
+    /// Set to high after received data was dropped
+    overrun_error: bool,
+    /// Number of dropped bytes
+    dropped: Bits<16>,
 }
 
 impl UartState {
-    const fn default() -> Self {
+    pub const fn default() -> Self {
         UartState {
+            synchronizer: <Synchronizer<2> as Synchronous>::INITIAL_STATE,
+            cts_synchronizer: <Synchronizer<2> as Synchronous>::INITIAL_STATE,
             receiver: UartReceiverState::default(),
             sender: UartSenderState::default(),
+            paused: false,
+            xoff_sent: false,
+            escaped: false,
+            pending: bits::<8>(0),
+            pending_valid: false,
+            overrun_error: false,
+            dropped: bits::<16>(0),
         }
     }
 }
@@ -81,37 +432,160 @@ pub fn uart_update(params: Uart, state: UartState, input: UartInput) -> (UartSta
     note("input", input);
     // TODO: Allow note_push_path in rhdl-macro-core
     // For now this is only a uncommitted change
+    note_push_path("synchronizer");
+    let (synchronizer_state, synchronized_rx) =
+        synchronizer_update::<2>(params.synchronizer, state.synchronizer, input.rx);
+    note_pop_path();
+    note_push_path("cts_synchronizer");
+    let (cts_synchronizer_state, synchronized_cts) =
+        synchronizer_update::<2>(params.synchronizer, state.cts_synchronizer, input.cts);
+    note_pop_path();
+    let clear_to_send = synchronized_cts | !params.flow_control;
+    // Software flow control. Control characters are taken by the Uart itself, even while
+    // `rx_ready` is low. A control character that arrives while `rx_ready` is low waits in the
+    // receiver for one cycle.
+    let (waiting, waiting_data) = uart_receiver_waiting(state.receiver);
+    let waiting_control = params.software_flow_control
+        & waiting
+        & !state.escaped
+        & is_control_character(waiting_data);
+    let receiver_ready = input.rx_ready | waiting_control;
     note_push_path("receiver");
     let (receiver_state, receiver_output) = uart_receiver_update(
         params.receiver,
         state.receiver,
         UartReceiverInput {
             reset: input.reset,
-            rs232: input.rx,
+            rs232: if params.synchronize_rx {
+                synchronized_rx
+            } else {
+                input.rx
+            },
+            divisor: input.divisor,
+            set_divisor: input.set_divisor,
+            ready: receiver_ready,
         },
     );
     note_pop_path();
+
+    // Control characters are not passed on and the byte after the escape character is restored
+    let received = params.software_flow_control & receiver_output.valid;
+    let received_control = received & !state.escaped;
+    let received_xon = received_control & (receiver_output.data == bits::<8>(0x11));
+    let received_xoff = received_control & (receiver_output.data == bits::<8>(0x13));
+    let received_escape = received_control & (receiver_output.data == bits::<8>(0x7D));
+    let paused = if received_xoff & receiver_ready {
+        true
+    } else if received_xon & receiver_ready {
+        false
+    } else {
+        state.paused
+    };
+    let send_xoff = params.software_flow_control & input.rx_almost_full & !state.xoff_sent;
+    let send_xon = params.software_flow_control & !input.rx_almost_full & state.xoff_sent;
+    let send_control = send_xoff | send_xon;
+    let escape_data = params.software_flow_control & is_control_character(input.data);
+    // The escaped byte and the control characters go first. New data is only taken if nothing
+    // else is waiting.
+    let accept = clear_to_send & !state.pending_valid & !send_control & !state.paused;
+    let sender_valid =
+        (clear_to_send & (state.pending_valid | send_control)) | (accept & input.valid);
+
     note_push_path("sender");
     let (sender_state, sender_output) = uart_sender_update(
         params.sender,
         state.sender,
         UartSenderInput {
             reset: input.reset,
-            data: input.data,
-            ready: input.start,
+            data: if state.pending_valid {
+                state.pending
+            } else if send_xoff {
+                bits::<8>(0x13)
+            } else if send_xon {
+                bits::<8>(0x11)
+            } else if escape_data {
+                bits::<8>(0x7D)
+            } else {
+                input.data
+            },
+            ninth_bit: accept & input.ninth_bit,
+            valid: sender_valid,
+            divisor: input.divisor,
+            set_divisor: input.set_divisor,
         },
     );
     note_pop_path();
+    let sent = sender_valid & sender_output.ready;
+    let sent_pending = sent & state.pending_valid;
+    let sent_control = sent & !state.pending_valid & send_control;
+    let taken = sender_output.ready & accept & input.valid;
 
     let next_state: UartState = UartState {
+        synchronizer: synchronizer_state,
+        cts_synchronizer: cts_synchronizer_state,
         receiver: receiver_state,
         sender: sender_state,
+        paused: !input.reset & paused,
+        xoff_sent: if input.reset {
+            false
+        } else if sent_control {
+            send_xoff
+        } else {
+            state.xoff_sent
+        },
+        escaped: if input.reset {
+            false
+        } else if received & receiver_ready {
+            received_escape
+        } else {
+            state.escaped
+        },
+        pending: if taken {
+            input.data ^ bits::<8>(0x20)
+        } else {
+            state.pending
+        },
+        pending_valid: if input.reset {
+            false
+        } else if taken {
+            escape_data
+        } else {
+            state.pending_valid & !sent_pending
+        },
+        // A new overrun wins over clearing the error in the same cycle
+        overrun_error: if input.reset {
+            false
+        } else if receiver_output.overrun {
+            true
+        } else {
+            state.overrun_error & !input.clear_overrun
+        },
+        dropped: if input.reset {
+            bits::<16>(0)
+        } else if receiver_output.overrun & (state.dropped != bits::<16>(0xFFFF)) {
+            state.dropped + 1
+        } else {
+            state.dropped
+        },
     };
     let output = UartOutput {
-        received_data: receiver_output.data,
-        valid: receiver_output.valid,
-        ready: sender_output.ready,
+        received_data: if state.escaped {
+            receiver_output.data ^ bits::<8>(0x20)
+        } else {
+            receiver_output.data
+        },
+        received_ninth_bit: receiver_output.ninth_bit,
+        valid: receiver_output.valid & !(received_xon | received_xoff | received_escape),
+        parity_error: receiver_output.parity_error,
+        framing_error: receiver_output.framing_error,
+        break_detected: receiver_output.break_detected,
+        ready: sender_output.ready & accept,
         tx: sender_output.rs232,
+        divisor: receiver_output.divisor,
+        rts: !(params.flow_control & input.rx_almost_full),
+        overrun: receiver_output.overrun,
+        overrun_error: state.overrun_error,
+        dropped: state.dropped,
     };
 
     note("next_state", next_state);
@@ -120,12 +594,36 @@ pub fn uart_update(params: Uart, state: UartState, input: UartInput) -> (UartSta
     (next_state, output)
 }
 
+/// The bare Uart echoes every received byte
+impl SerialDesign for Uart {
+    fn serial_input(rx: bool, previous_output: &UartOutput) -> UartInput {
+        UartInput {
+            reset: false,
+            rx,
+            data: previous_output.received_data,
+            ninth_bit: previous_output.received_ninth_bit,
+            valid: previous_output.valid,
+            divisor: Default::default(),
+            set_divisor: false,
+            cts: true,
+            rx_ready: true,
+            clear_overrun: false,
+            rx_almost_full: false,
+        }
+    }
+
+    fn serial_output(output: &UartOutput) -> bool {
+        output.tx
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::uart::Uart;
 
-    use super::{UartInput, UartOutput};
-    use rhdl::synchronous::{simulate_first_cycle, simulate_one_cycle};
+    use super::{UartConfigError, UartInput, UartOutput};
+    use crate::serial_harness::{deserialize, serialize, SerialHarness};
+    use rhdl::synchronous::{simulate, simulate_first_cycle, simulate_one_cycle};
     use rhdl_bits::bits;
     use rhdl_core::ClockDetails;
     use rhdl_core::{note_init_db, note_take};
@@ -134,10 +632,17 @@ mod test {
     impl UartInput {
         fn new() -> Self {
             UartInput {
-                reset: true,
+                reset: false,
                 rx: true,
                 data: Default::default(),
-                start: false,
+                ninth_bit: false,
+                valid: false,
+                divisor: Default::default(),
+                set_divisor: false,
+                cts: true,
+                rx_ready: true,
+                clear_overrun: false,
+                rx_almost_full: false,
             }
         }
 
@@ -146,7 +651,14 @@ mod test {
                 reset: true,
                 rx: true,
                 data: Default::default(),
-                start: false,
+                ninth_bit: false,
+                valid: false,
+                divisor: Default::default(),
+                set_divisor: false,
+                cts: true,
+                rx_ready: true,
+                clear_overrun: false,
+                rx_almost_full: false,
             }
         }
 
@@ -155,7 +667,14 @@ mod test {
                 reset: self.reset,
                 rx: previous_output.tx,
                 data: self.data,
-                start: self.start,
+                ninth_bit: self.ninth_bit,
+                valid: self.valid,
+                divisor: self.divisor,
+                set_divisor: self.set_divisor,
+                cts: self.cts,
+                rx_ready: self.rx_ready,
+                clear_overrun: self.clear_overrun,
+                rx_almost_full: self.rx_almost_full,
             }
         }
     }
@@ -193,7 +712,9 @@ mod test {
                 reset: false,
                 rx: true,
                 data: bits::<8>(0b010100011),
-                start: true,
+                ninth_bit: false,
+                valid: true,
+                ..UartInput::new()
             },
             state,
             time,
@@ -219,6 +740,226 @@ mod test {
         assert!(output_with_valid_input.0.received_data == bits::<8>(0b010100011));
     }
 
+    #[test]
+    fn test_uart_synchronizes_rx() {
+        let line = std::iter::repeat(true)
+            .take(4)
+            .chain(serialize(&[0x42], 4))
+            .chain(std::iter::repeat(true).take(8))
+            .collect::<Vec<_>>();
+        let input = line.iter().map(|rx| UartInput {
+            rx: *rx,
+            ..UartInput::new()
+        });
+        let valid_cycle = |uart: Uart| {
+            simulate(uart, input.clone())
+                .position(|output| output.valid)
+                .unwrap()
+        };
+
+        let uart = Uart::new(9600 * 4, 9600);
+        // Both flops of the synchronizer delay the line by one cycle
+        assert_eq!(
+            valid_cycle(uart),
+            valid_cycle(uart.without_synchronizer()) + 2
+        );
+    }
+
+    #[test]
+    fn test_uart_try_new() {
+        assert!(Uart::try_new(12_000_000, 9600).is_ok());
+        assert!(Uart::try_new(12_000_000, 921600).is_ok());
+        assert!(matches!(
+            Uart::try_new(1, 9600),
+            Err(UartConfigError::DivisorTooSmall { .. })
+        ));
+        assert!(matches!(
+            Uart::try_new(9600, 0),
+            Err(UartConfigError::ZeroBitRate)
+        ));
+        assert!(matches!(
+            Uart::try_new(1 << 40, 9),
+            Err(UartConfigError::DivisorTooLarge { .. })
+        ));
+        // 2.5 clock cycles per bit are off by a fifth of a bit
+        assert!(matches!(
+            Uart::try_new(9600 * 5, 9600 * 2),
+            Err(UartConfigError::BaudErrorTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_uart_flow_control() {
+        let uart = Uart::new(9600 * 4, 9600).with_flow_control();
+        let input = |valid: bool, cts: bool, rx_almost_full: bool| UartInput {
+            data: bits::<8>(0x42),
+            valid,
+            cts,
+            rx_almost_full,
+            ..UartInput::new()
+        };
+        // CTS needs two cycles to pass the synchronizer
+        let inputs = std::iter::repeat(input(false, false, true))
+            .take(4)
+            .chain(std::iter::repeat(input(true, false, true)).take(36))
+            .chain(std::iter::repeat(input(true, true, false)).take(60));
+        let outputs = simulate(uart, inputs).collect::<Vec<_>>();
+
+        // The sender holds off while CTS is low
+        assert!(outputs[..40].iter().all(|output| output.tx));
+        assert!(outputs[2..40].iter().all(|output| !output.ready));
+        assert!(outputs[..40].iter().all(|output| !output.rts));
+        assert!(outputs[40..].iter().all(|output| output.rts));
+        let tx = outputs.iter().map(|output| output.tx).collect::<Vec<_>>();
+        let received = deserialize(&tx, 4);
+        assert_eq!(received[0].byte, 0x42);
+
+        // Without flow control CTS is ignored and RTS stays high
+        let outputs = simulate(
+            Uart::new(9600 * 4, 9600),
+            std::iter::repeat(input(true, false, true)).take(10),
+        )
+        .collect::<Vec<_>>();
+        assert!(outputs.iter().any(|output| !output.tx));
+        assert!(outputs.iter().all(|output| output.rts));
+    }
+
+    #[test]
+    fn test_uart_counts_dropped_bytes() {
+        let uart = Uart::new(9600 * 4, 9600).without_synchronizer();
+        let line = [vec![true; 4], serialize(&[0x41, 0x42, 0x43], 4)].concat();
+        let input = line
+            .iter()
+            .map(|rx| UartInput {
+                rx: *rx,
+                rx_ready: false,
+                ..UartInput::new()
+            })
+            .chain([
+                UartInput {
+                    clear_overrun: true,
+                    ..UartInput::new()
+                },
+                UartInput::new(),
+            ]);
+        let outputs = simulate(uart, input).collect::<Vec<_>>();
+
+        // The first byte waits, the other two are dropped
+        assert_eq!(outputs.iter().filter(|output| output.overrun).count(), 2);
+        let waiting = &outputs[line.len()];
+        assert!(waiting.valid);
+        assert_eq!(waiting.received_data, bits::<8>(0x41));
+        assert!(waiting.overrun_error);
+        assert_eq!(waiting.dropped, bits::<16>(2));
+        // Clearing the error keeps the count
+        let cleared = outputs.last().unwrap();
+        assert!(!cleared.overrun_error);
+        assert_eq!(cleared.dropped, bits::<16>(2));
+    }
+
+    #[test]
+    fn test_uart_software_flow_control_escapes() {
+        let uart = Uart::new(9600 * 4, 9600).with_software_flow_control();
+        let input = [0x42, 0x11, 0x7D].iter().flat_map(|byte| {
+            std::iter::once(UartInput {
+                data: bits::<8>(*byte),
+                valid: true,
+                ..UartInput::new()
+            })
+            .chain(std::iter::repeat(UartInput::new()).take(99))
+        });
+        let tx = simulate(uart, input)
+            .map(|output| output.tx)
+            .collect::<Vec<_>>();
+
+        let sent = deserialize(&tx, 4)
+            .iter()
+            .map(|received| received.byte)
+            .collect::<Vec<_>>();
+        assert_eq!(sent, [0x42, 0x7D, 0x31, 0x7D, 0x5D]);
+    }
+
+    #[test]
+    fn test_uart_software_flow_control_receives() {
+        let uart = Uart::new(9600 * 4, 9600)
+            .without_synchronizer()
+            .with_software_flow_control();
+        let line = [
+            vec![true; 4],
+            serialize(&[0x13, 0x42, 0x7D, 0x33, 0x11], 4),
+            vec![true; 8],
+        ]
+        .concat();
+        let input = line.iter().map(|rx| UartInput {
+            rx: *rx,
+            ..UartInput::new()
+        });
+        let outputs = simulate(uart, input).collect::<Vec<_>>();
+
+        let received = outputs
+            .iter()
+            .filter(|output| output.valid)
+            .map(|output| output.received_data.0)
+            .collect::<Vec<_>>();
+        assert_eq!(received, [0x42, 0x13]);
+        // The sender is paused between XOFF and XON
+        let first_valid = outputs.iter().position(|output| output.valid).unwrap();
+        assert!(!outputs[first_valid].ready);
+        assert!(outputs.last().unwrap().ready);
+    }
+
+    #[test]
+    fn test_uart_software_flow_control_while_not_ready() {
+        let uart = Uart::new(9600 * 4, 9600)
+            .without_synchronizer()
+            .with_software_flow_control();
+        let line = [
+            vec![true; 4],
+            serialize(&[0x13], 4),
+            vec![true; 60],
+            serialize(&[0x11], 4),
+            vec![true; 60],
+        ]
+        .concat();
+        // Nothing is taken from the receiver, but a byte waits to be sent once XOFF was received
+        let input = line.iter().enumerate().map(|(cycle, rx)| UartInput {
+            rx: *rx,
+            data: bits::<8>(0x42),
+            valid: cycle >= 50,
+            rx_ready: false,
+            ..UartInput::new()
+        });
+        let outputs = simulate(uart, input).collect::<Vec<_>>();
+
+        assert!(outputs.iter().all(|output| !output.valid));
+        let tx = outputs.iter().map(|output| output.tx).collect::<Vec<_>>();
+        let sent = deserialize(&tx, 4);
+        assert!(!sent.is_empty());
+        assert!(sent.iter().all(|received| received.byte == 0x42));
+        // The sender only starts after XON
+        assert!(sent[0].cycle > 4 + 40 + 60 + 36);
+    }
+
+    #[test]
+    fn test_uart_software_flow_control_sends_xoff() {
+        let uart = Uart::new(9600 * 4, 9600).with_software_flow_control();
+        let input = |rx_almost_full: bool| UartInput {
+            rx_almost_full,
+            ..UartInput::new()
+        };
+        let inputs = std::iter::repeat(input(true))
+            .take(60)
+            .chain(std::iter::repeat(input(false)).take(60));
+        let outputs = simulate(uart, inputs).collect::<Vec<_>>();
+
+        let tx = outputs.iter().map(|output| output.tx).collect::<Vec<_>>();
+        let sent = deserialize(&tx, 4)
+            .iter()
+            .map(|received| received.byte)
+            .collect::<Vec<_>>();
+        assert_eq!(sent, [0x13, 0x11]);
+    }
+
     #[test]
     fn test_uart_sender_speed_1() {
         test_uart_at_speed(1);
@@ -235,4 +976,18 @@ mod test {
     fn test_uart_sender_speed_4() {
         test_uart_at_speed(4);
     }
+
+    #[test]
+    fn test_uart_echo() {
+        for clocks_per_bit in 1..=4 {
+            let harness = SerialHarness::new(clocks_per_bit);
+            let uart = Uart::new(9600 * clocks_per_bit as u128, 9600);
+            assert_eq!(
+                harness.transfer(uart, &[&[0xA3], &[0x00], &[0xFF], b"x"]),
+                [0xA3, 0x00, 0xFF, b'x'],
+                "Echo failed with {} clocks per bit",
+                clocks_per_bit
+            );
+        }
+    }
 }
@@ -0,0 +1,171 @@
+use rhdl::{bits::bits, kernel, Bits, Digital};
+use rhdl_core::{note, Synchronous};
+
+/// Number of fractional bits in the phase accumulator
+const FRACTION_BITS: u32 = 16;
+/// The fractional bits of the phase. The phase overflowed if it is larger.
+const FRACTION_MASK: u128 = (1 << FRACTION_BITS) - 1;
+/// The phase at the start of every frame, half a clock cycle
+const START_PHASE: u128 = 1 << (FRACTION_BITS - 1);
+/// The longest frame has a start bit, 9 data bits, a parity bit and 2 stop bits
+const FRAME_BITS: u128 = 13;
+
+/// Generates the length of every bit for bit rates that do not divide the clock speed.
+///
+/// A bit is `clock_speed / bit_rate` clock cycles long, rounded down. The remainder is added to a
+/// phase accumulator for every bit. Whenever the accumulator overflows, the bit is one cycle
+/// longer. The bit edges stay within half a cycle of their ideal position instead of drifting away
+/// over the frame.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BaudGenerator {
+    /// Whole clock cycles per bit
+    bitlength: Bits<32>,
+    /// Fractional clock cycles per bit in 1/65536 cycles
+    fraction: Bits<32>,
+}
+
+impl BaudGenerator {
+    /// Create a new BaudGenerator with a given clock speed and bit rate.
+    pub fn new(clock_speed: u128, bit_rate: u128) -> Self {
+        let (bitlength, fraction) = divisor(clock_speed, bit_rate);
+        BaudGenerator {
+            bitlength: Bits(bitlength),
+            fraction: Bits(fraction),
+        }
+    }
+}
+
+/// Split `clock_speed / bit_rate` into whole cycles and 1/65536 cycles.
+fn divisor(clock_speed: u128, bit_rate: u128) -> (u128, u128) {
+    let scaled = (clock_speed << FRACTION_BITS) / bit_rate;
+    (scaled >> FRACTION_BITS, scaled & FRACTION_MASK)
+}
+
+/// Calculate the worst-case timing error of the bit edges in a frame in percent of a bit.
+///
+/// This is the largest difference between the end of a bit generated by the BaudGenerator and
+/// its ideal position, over the longest possible frame.
+pub fn baud_error(clock_speed: u128, bit_rate: u128) -> f64 {
+    let (bitlength, fraction) = divisor(clock_speed, bit_rate);
+    let ideal_bitlength = clock_speed as f64 / bit_rate as f64;
+    let mut phase = START_PHASE;
+    let mut edge = 0;
+    (1..=FRAME_BITS)
+        .map(|bit| {
+            phase += fraction;
+            edge += bitlength + (phase >> FRACTION_BITS);
+            phase &= FRACTION_MASK;
+            let ideal_edge = bit as f64 * ideal_bitlength;
+            (edge as f64 - ideal_edge).abs() / ideal_bitlength * 100.0
+        })
+        .fold(0.0, f64::max)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BaudGeneratorInput {
+    /// Set to high in the first cycle of a frame
+    pub restart: bool,
+    /// Set to high in the first cycle of every other bit of the frame
+    pub next: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
+pub struct BaudGeneratorOutput {
+    /// Length of the bit that starts in this cycle in clock cycles
+    pub bitlength: Bits<32>,
+}
+
+impl Synchronous for BaudGenerator {
+    type Input = BaudGeneratorInput;
+    type Output = BaudGeneratorOutput;
+    type State = Bits<32>;
+    type Update = baud_generator_update;
+
+    const INITIAL_STATE: Self::State = Bits(0);
+    const UPDATE: fn(Self, Self::State, Self::Input) -> (Self::State, Self::Output) =
+        baud_generator_update;
+}
+
+#[kernel]
+pub fn baud_generator_update(
+    params: BaudGenerator,
+    state: Bits<32>,
+    input: BaudGeneratorInput,
+) -> (Bits<32>, BaudGeneratorOutput) {
+    note("input", input);
+
+    // Every frame starts at half a cycle, so the bit edges are rounded to the nearest cycle
+    let phase = if input.restart {
+        bits::<32>(START_PHASE)
+    } else {
+        state
+    };
+    let sum = phase + params.fraction;
+    let next_state = if input.restart | input.next {
+        sum & bits::<32>(FRACTION_MASK)
+    } else {
+        state
+    };
+    let output = BaudGeneratorOutput {
+        bitlength: if sum > bits::<32>(FRACTION_MASK) {
+            params.bitlength + 1
+        } else {
+            params.bitlength
+        },
+    };
+
+    note("next_state", next_state);
+    note("output", output);
+
+    (next_state, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{baud_error, BaudGenerator, BaudGeneratorInput};
+    use itertools::Itertools;
+    use rhdl::synchronous::simulate;
+
+    /// The lengths of the bits of a frame with `count` bits
+    fn bit_lengths(baud_generator: BaudGenerator, count: usize) -> Vec<u128> {
+        let input = (0..count).map(|bit| BaudGeneratorInput {
+            restart: bit == 0,
+            next: bit != 0,
+        });
+        simulate(baud_generator, input)
+            .map(|output| output.bitlength.0)
+            .collect_vec()
+    }
+
+    #[test]
+    fn test_baud_generator_whole_divisor() {
+        let lengths = bit_lengths(BaudGenerator::new(9600 * 4, 9600), 10);
+        assert_eq!(lengths, [4; 10]);
+    }
+
+    #[test]
+    fn test_baud_generator_fraction() {
+        // 4.5 cycles per bit
+        let lengths = bit_lengths(BaudGenerator::new(9600 * 9, 9600 * 2), 6);
+        assert_eq!(lengths, [5, 4, 5, 4, 5, 4]);
+
+        // 104.17 cycles per bit, every edge is rounded to the nearest cycle
+        let lengths = bit_lengths(BaudGenerator::new(12_000_000, 115200), 13);
+        let mut edge = 0;
+        for (bit, length) in lengths.iter().enumerate() {
+            edge += length;
+            let ideal_edge = (bit + 1) as f64 * 12_000_000.0 / 115200.0;
+            assert!((edge as f64 - ideal_edge).abs() < 0.51);
+        }
+    }
+
+    #[test]
+    fn test_baud_error() {
+        assert_eq!(baud_error(9600 * 4, 9600), 0.0);
+        // Integer division would be off by 1.7 cycles after 10 bits
+        assert!(baud_error(12_000_000, 115200) < 0.5);
+        assert!(baud_error(12_000_000, 921600) < 4.0);
+        // Half a cycle of 4.5 cycles
+        assert!((baud_error(9600 * 9, 9600 * 2) - 100.0 / 9.0).abs() < 0.01);
+    }
+}
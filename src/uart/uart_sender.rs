@@ -1,7 +1,13 @@
 use rhdl::{bits::bits, kernel, Bits, Digital};
-use rhdl_core::{note, Synchronous};
+use rhdl_core::{note, note_pop_path, note_push_path, Synchronous};
 use rhdl_std::get_bit;
 
+use super::baud_generator::{baud_generator_update, BaudGenerator, BaudGeneratorInput};
+use super::{
+    check_bit_rate, check_divisor, parity_bit, stop_length, UartConfigError, UartFormat,
+    UartParity, UartStopBits,
+};
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
 pub struct UartSender {
     // TODO: Crashes when generating verilog and there are no fields in the struct
@@ -9,17 +15,59 @@ pub struct UartSender {
     bitlength: Bits<32>,
     /// Duration of a half bit in clock cycles
     half_bitlength: Bits<32>,
+    /// Number of stop bits
+    stop_bits: UartStopBits,
+    /// Number of data bits in a frame
+    data_bits: u8,
+    /// The parity bit to send
+    parity: UartParity,
+    /// Generates the bit lengths if the bit rate does not divide the clock speed
+    baud: BaudGenerator,
 }
 
 impl UartSender {
     /// Create a new UartSender with a given clock speed and bit rate.
     #[allow(dead_code)]
     pub fn new(clock_speed: u128, bit_rate: u128) -> Self {
+        UartSender::with_format(clock_speed, bit_rate, UartFormat::default())
+    }
+
+    /// Create a new UartSender with a given clock speed, bit rate and frame format.
+    ///
+    /// Panics if a bit is shorter than a clock cycle or too long for the counters.
+    pub fn with_format(clock_speed: u128, bit_rate: u128, format: UartFormat) -> Self {
+        if let Err(error) = check_divisor(clock_speed, bit_rate) {
+            panic!("{}", error);
+        }
         UartSender {
             bitlength: Bits(clock_speed / bit_rate),
             half_bitlength: Bits(((clock_speed / bit_rate) - 1) / 2),
+            stop_bits: format.stop_bits,
+            data_bits: format.data_bits,
+            parity: format.parity,
+            baud: BaudGenerator::new(clock_speed, bit_rate),
         }
     }
+
+    /// Create a new UartSender with a given clock speed and bit rate.
+    ///
+    /// Fails if the bit rate can not be generated from the clock speed, see [check_bit_rate].
+    #[allow(dead_code)]
+    pub fn try_new(clock_speed: u128, bit_rate: u128) -> Result<Self, UartConfigError> {
+        UartSender::try_with_format(clock_speed, bit_rate, UartFormat::default())
+    }
+
+    /// Create a new UartSender with a given clock speed, bit rate and frame format.
+    ///
+    /// Fails if the bit rate can not be generated from the clock speed, see [check_bit_rate].
+    pub fn try_with_format(
+        clock_speed: u128,
+        bit_rate: u128,
+        format: UartFormat,
+    ) -> Result<Self, UartConfigError> {
+        check_bit_rate(clock_speed, bit_rate)?;
+        Ok(UartSender::with_format(clock_speed, bit_rate, format))
+    }
 }
 
 // tag::interface[]
@@ -29,15 +77,27 @@ pub struct UartSenderInput {
     pub reset: bool,
     /// Bit to send
     pub data: Bits<8>,
-    /// Set to high to start the transmission
+    /// Ninth bit to send. Only used with 9 data bits.
+    pub ninth_bit: bool,
+    /// Set to high if `data` should be sent
     ///
-    /// Only works if the sender is ready
-    pub ready: bool,
+    /// The byte is taken in the cycle where both `valid` and `ready` are high. Keep `data` and
+    /// `valid` until then.
+    pub valid: bool,
+    /// New bit length in clock cycles
+    ///
+    /// Zero restores the bit length from the parameters.
+    pub divisor: Bits<32>,
+    /// Set to high for one cycle to load `divisor`
+    ///
+    /// The new bit length is used from the next bit on.
+    pub set_divisor: bool,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Digital, Default)]
 pub struct UartSenderOutput {
-    /// Set to high if the Sender is ready for the next byte
+    /// High when a byte can be accepted this cycle; the byte is taken when `valid` and `ready` are
+    /// both high.
     pub ready: bool,
     /// rs232 data input
     pub rs232: bool,
@@ -52,6 +112,7 @@ pub enum UartSenderStateEnum {
     Idle,
     Start,
     Data(u8),
+    Parity,
     Stop,
 }
 
@@ -61,6 +122,14 @@ pub struct UartSenderState {
     counter: Bits<32>,
     /// The data we will be sending
     data: Bits<8>,
+    /// The ninth bit we will be sending
+    ninth_bit: bool,
+    /// XOR of all data bits sent so far
+    odd_ones: bool,
+    /// The bit length in clock cycles or zero to use the one from the parameters
+    divisor: Bits<32>,
+    /// The phase of the baud generator
+    baud: Bits<32>,
     /// The current state of the sender
     state: UartSenderStateEnum,
 }
@@ -70,6 +139,10 @@ impl UartSenderState {
     pub const fn default() -> Self {
         UartSenderState {
             data: bits::<8>(0),
+            ninth_bit: false,
+            odd_ones: false,
+            divisor: Bits(0),
+            baud: <BaudGenerator as Synchronous>::INITIAL_STATE,
             state: UartSenderStateEnum::Idle,
             counter: Bits(0),
         }
@@ -89,6 +162,16 @@ impl Synchronous for UartSender {
 }
 // end::synchronous[]
 
+/// Get the data bit with the given index. Index 8 is the ninth bit.
+#[kernel]
+pub fn data_bit(data: Bits<8>, ninth_bit: bool, index: u8) -> bool {
+    if index == 8 {
+        ninth_bit
+    } else {
+        get_bit::<8>(data, index)
+    }
+}
+
 // TODO: Figure out how to use set_bit with a Bits index
 // tag::update[]
 #[kernel]
@@ -98,95 +181,201 @@ pub fn uart_sender_update(
     input: UartSenderInput,
 ) -> (UartSenderState, UartSenderOutput) {
     note("input_data", input.data);
-    note("input_valid", input.ready);
-
-    let next_state: UartSenderState = match state.state {
-        UartSenderStateEnum::Idle => {
-            if input.ready {
-                UartSenderState {
-                    data: input.data,
-                    state: UartSenderStateEnum::Start,
-                    counter: params.bitlength - 1,
-                }
-            } else {
-                UartSenderState {
-                    data: state.data,
-                    state: state.state,
-                    counter: state.counter,
-                }
-            }
+    note("input_valid", input.valid);
+
+    let divisor = if input.set_divisor {
+        input.divisor
+    } else {
+        state.divisor
+    };
+    // A new bit starts whenever the counter of the last one runs out
+    let next_bit = (state.counter == 0)
+        & (state.state != UartSenderStateEnum::Idle)
+        & (state.state != UartSenderStateEnum::Stop);
+    note_push_path("baud_generator");
+    let (baud_state, baud_output) = baud_generator_update(
+        params.baud,
+        state.baud,
+        BaudGeneratorInput {
+            restart: (state.state == UartSenderStateEnum::Idle) & input.valid,
+            next: next_bit,
+        },
+    );
+    note_pop_path();
+    // The length of the bit that starts in this cycle. The bit length in the state replaces the
+    // generated one.
+    let next_bitlength = if state.divisor == 0 {
+        baud_output.bitlength
+    } else {
+        state.divisor
+    };
+
+    let next_state: UartSenderState = if input.reset {
+        // Stop sending and restore the bit length from the parameters
+        UartSenderState {
+            data: bits::<8>(0),
+            ninth_bit: false,
+            odd_ones: false,
+            divisor: bits::<32>(0),
+            baud: baud_state,
+            state: UartSenderStateEnum::Idle,
+            counter: bits::<32>(0),
         }
-        UartSenderStateEnum::Start => {
-            if state.counter == 0 {
-                UartSenderState {
-                    data: state.data,
-                    state: UartSenderStateEnum::Data(0),
-                    counter: params.bitlength - 1,
+    } else {
+        match state.state {
+            UartSenderStateEnum::Idle => {
+                if input.valid {
+                    UartSenderState {
+                        data: input.data,
+                        ninth_bit: input.ninth_bit,
+                        odd_ones: false,
+                        divisor,
+                        baud: baud_state,
+                        state: UartSenderStateEnum::Start,
+                        counter: next_bitlength - 1,
+                    }
+                } else {
+                    UartSenderState {
+                        data: state.data,
+                        ninth_bit: state.ninth_bit,
+                        odd_ones: state.odd_ones,
+                        divisor,
+                        baud: baud_state,
+                        state: state.state,
+                        counter: state.counter,
+                    }
                 }
-            } else {
-                UartSenderState {
-                    data: state.data,
-                    state: UartSenderStateEnum::Start,
-                    counter: state.counter - 1,
+            }
+            UartSenderStateEnum::Start => {
+                if state.counter == 0 {
+                    UartSenderState {
+                        data: state.data,
+                        ninth_bit: state.ninth_bit,
+                        odd_ones: state.odd_ones,
+                        divisor,
+                        baud: baud_state,
+                        state: UartSenderStateEnum::Data(0),
+                        counter: next_bitlength - 1,
+                    }
+                } else {
+                    UartSenderState {
+                        data: state.data,
+                        ninth_bit: state.ninth_bit,
+                        odd_ones: state.odd_ones,
+                        divisor,
+                        baud: baud_state,
+                        state: UartSenderStateEnum::Start,
+                        counter: state.counter - 1,
+                    }
                 }
             }
-        }
-        UartSenderStateEnum::Stop => {
-            if state.counter == 0 {
-                UartSenderState {
-                    data: state.data,
-                    state: UartSenderStateEnum::Idle,
-                    counter: params.bitlength - 1,
+            UartSenderStateEnum::Stop => {
+                if state.counter == 0 {
+                    UartSenderState {
+                        data: state.data,
+                        ninth_bit: state.ninth_bit,
+                        odd_ones: state.odd_ones,
+                        divisor,
+                        baud: baud_state,
+                        state: UartSenderStateEnum::Idle,
+                        counter: next_bitlength - 1,
+                    }
+                } else {
+                    UartSenderState {
+                        data: state.data,
+                        ninth_bit: state.ninth_bit,
+                        odd_ones: state.odd_ones,
+                        divisor,
+                        baud: baud_state,
+                        state: UartSenderStateEnum::Stop,
+                        counter: state.counter - 1,
+                    }
                 }
-            } else {
-                UartSenderState {
-                    data: state.data,
-                    state: UartSenderStateEnum::Stop,
-                    counter: state.counter - 1,
+            }
+            UartSenderStateEnum::Data(index) => {
+                if state.counter == 0 {
+                    let odd_ones = state.odd_ones ^ data_bit(state.data, state.ninth_bit, index);
+                    if index + 1 == params.data_bits {
+                        if params.parity == UartParity::None {
+                            UartSenderState {
+                                data: state.data,
+                                ninth_bit: state.ninth_bit,
+                                odd_ones,
+                                divisor,
+                                baud: baud_state,
+                                state: UartSenderStateEnum::Stop,
+                                counter: stop_length(params.stop_bits, next_bitlength) - 1,
+                            }
+                        } else {
+                            UartSenderState {
+                                data: state.data,
+                                ninth_bit: state.ninth_bit,
+                                odd_ones,
+                                divisor,
+                                baud: baud_state,
+                                state: UartSenderStateEnum::Parity,
+                                counter: next_bitlength - 1,
+                            }
+                        }
+                    } else {
+                        UartSenderState {
+                            data: state.data,
+                            ninth_bit: state.ninth_bit,
+                            odd_ones,
+                            divisor,
+                            baud: baud_state,
+                            state: UartSenderStateEnum::Data(index + 1),
+                            counter: next_bitlength - 1,
+                        }
+                    }
+                } else {
+                    UartSenderState {
+                        data: state.data,
+                        ninth_bit: state.ninth_bit,
+                        odd_ones: state.odd_ones,
+                        divisor,
+                        baud: baud_state,
+                        state: UartSenderStateEnum::Data(index),
+                        counter: state.counter - 1,
+                    }
                 }
             }
-        }
-        UartSenderStateEnum::Data(index) => {
-            if state.counter == 0 {
-                if index == 7 {
+            UartSenderStateEnum::Parity => {
+                if state.counter == 0 {
                     UartSenderState {
                         data: state.data,
+                        ninth_bit: state.ninth_bit,
+                        odd_ones: state.odd_ones,
+                        divisor,
+                        baud: baud_state,
                         state: UartSenderStateEnum::Stop,
-                        counter: params.bitlength - 1,
+                        counter: stop_length(params.stop_bits, next_bitlength) - 1,
                     }
                 } else {
                     UartSenderState {
                         data: state.data,
-                        state: UartSenderStateEnum::Data(index + 1),
-                        counter: params.bitlength - 1,
+                        ninth_bit: state.ninth_bit,
+                        odd_ones: state.odd_ones,
+                        divisor,
+                        baud: baud_state,
+                        state: UartSenderStateEnum::Parity,
+                        counter: state.counter - 1,
                     }
                 }
-            } else {
-                UartSenderState {
-                    data: state.data,
-                    state: UartSenderStateEnum::Data(index),
-                    counter: state.counter - 1,
-                }
             }
         }
     };
 
-    let output = match next_state.state {
-        UartSenderStateEnum::Idle => UartSenderOutput {
-            ready: true,
-            rs232: true,
-        },
-        UartSenderStateEnum::Start => UartSenderOutput {
-            ready: false,
-            rs232: false,
-        },
-        UartSenderStateEnum::Data(index) => UartSenderOutput {
-            ready: false,
-            rs232: get_bit::<8>(next_state.data, index),
-        },
-        UartSenderStateEnum::Stop => UartSenderOutput {
-            ready: false,
-            rs232: true,
+    let output = UartSenderOutput {
+        ready: (state.state == UartSenderStateEnum::Idle) & !input.reset,
+        rs232: match next_state.state {
+            UartSenderStateEnum::Idle => true,
+            UartSenderStateEnum::Start => false,
+            UartSenderStateEnum::Data(index) => {
+                data_bit(next_state.data, next_state.ninth_bit, index)
+            }
+            UartSenderStateEnum::Parity => parity_bit(params.parity, next_state.odd_ones),
+            UartSenderStateEnum::Stop => true,
         },
     };
 
@@ -201,9 +390,10 @@ pub fn uart_sender_update(
 #[cfg(test)]
 mod test {
     use super::{UartSender, UartSenderInput};
+    use crate::uart::{UartConfigError, UartFormat, UartParity, UartStopBits};
     use itertools::{repeat_n, Itertools};
     use rhdl::bits::b8;
-    use rhdl::synchronous::simulate_with_clock;
+    use rhdl::synchronous::{simulate, simulate_with_clock};
     use rhdl_bits::bits;
     use rhdl_core::ClockDetails;
     use rhdl_core::{note_init_db, note_take};
@@ -217,7 +407,10 @@ mod test {
             UartSenderInput {
                 reset: false,
                 data: b8::default(),
-                ready: false,
+                ninth_bit: false,
+                valid: false,
+                divisor: Default::default(),
+                set_divisor: false,
             }
         }
 
@@ -225,7 +418,10 @@ mod test {
             UartSenderInput {
                 reset: true,
                 data: b8::default(),
-                ready: false,
+                ninth_bit: false,
+                valid: false,
+                divisor: Default::default(),
+                set_divisor: false,
             }
         }
 
@@ -233,7 +429,10 @@ mod test {
             UartSenderInput {
                 reset: false,
                 data: bits::<8>(data as u128),
-                ready: true,
+                ninth_bit: false,
+                valid: true,
+                divisor: Default::default(),
+                set_divisor: false,
             }
         }
     }
@@ -320,12 +519,212 @@ mod test {
         // Assert that the stop bit is high
         assert_eq!(results[middle_start + (9 * speed as usize)].0.rs232, true);
 
-        // Assert that ready goes high after the stop bit
+        // Assert that ready goes high in the cycle after the stop bit
+        assert_eq!(results[start_cycle + (10 * speed as usize)].0.ready, false);
+        assert_eq!(
+            results[start_cycle + (10 * speed as usize) + 1].0.ready,
+            true
+        );
+    }
+
+    /// Send a single frame at speed 4 and return the line for every cycle after the start
+    fn send_frame(data: u16, format: UartFormat) -> Vec<(bool, bool)> {
+        let uart_sender = UartSender::with_format(9600 * 4, 9600, format);
+        let input = uart_sender
+            .test_reset()
+            .chain(std::iter::once(UartSenderInput {
+                reset: false,
+                data: bits::<8>((data & 0xFF) as u128),
+                ninth_bit: data & 0x100 != 0,
+                valid: true,
+                divisor: Default::default(),
+                set_divisor: false,
+            }))
+            .chain(repeat_n(UartSenderInput::new(), 4 * 14));
+        simulate(uart_sender, input)
+            .skip(2)
+            .map(|output| (output.rs232, output.ready))
+            .collect_vec()
+    }
+
+    /// Sample the line in the middle of every bit
+    fn sample_bits(line: &[(bool, bool)], count: usize) -> Vec<bool> {
+        (0..count).map(|i| line[i * 4 + 2].0).collect_vec()
+    }
+
+    #[test]
+    fn test_uart_sender_7e1() {
+        let format = UartFormat::new(7, UartParity::Even, UartStopBits::One);
+        let line = send_frame(0x53, format);
+        // Start bit, 7 data bits LSB first, even parity and the stop bit
+        assert_eq!(
+            sample_bits(&line, 10),
+            [false, true, true, false, false, true, false, true, false, true]
+        );
+        assert!(!line[4 * 10].1);
+        assert!(line[4 * 10 + 1].1);
+    }
+
+    #[test]
+    fn test_uart_sender_8o2() {
+        let format = UartFormat::new(8, UartParity::Odd, UartStopBits::Two);
+        let line = send_frame(0xA5, format);
+        assert_eq!(
+            sample_bits(&line, 12),
+            [false, true, false, true, false, false, true, false, true, true, true, true]
+        );
+        assert!(!line[4 * 12].1);
+        assert!(line[4 * 12 + 1].1);
+    }
+
+    #[test]
+    fn test_uart_sender_one_and_a_half_stop_bits() {
+        let format = UartFormat::new(5, UartParity::Mark, UartStopBits::OneAndHalf);
+        let line = send_frame(0x1F, format);
+        assert_eq!(
+            sample_bits(&line, 8),
+            [false, true, true, true, true, true, true, true]
+        );
+        // Start bit, 5 data bits, parity and 1.5 stop bits
+        assert!(!line[4 * 8 + 2].1);
+        assert!(line[4 * 8 + 3].1);
+    }
+
+    #[test]
+    fn test_uart_sender_9_bits() {
+        let format = UartFormat::new(9, UartParity::Space, UartStopBits::One);
+        let line = send_frame(0x1A5, format);
+        assert_eq!(
+            sample_bits(&line, 12),
+            [false, true, false, true, false, false, true, false, true, true, false, true]
+        );
+    }
+
+    #[test]
+    fn test_uart_sender_set_divisor() {
+        let uart_sender = UartSender::new(9600 * 4, 9600);
+        let input = uart_sender
+            .test_reset()
+            .chain(std::iter::once(UartSenderInput {
+                divisor: bits::<32>(6),
+                set_divisor: true,
+                ..UartSenderInput::new()
+            }))
+            .chain(std::iter::once(UartSenderInput::transmit(0x53)))
+            .chain(repeat_n(UartSenderInput::new(), 6 * 11));
+        let line = simulate(uart_sender, input)
+            .skip(3)
+            .map(|output| output.rs232)
+            .collect_vec();
+
+        // Every bit is 6 cycles long now
+        assert_eq!(
+            (0..10).map(|i| line[i * 6 + 3]).collect_vec(),
+            [false, true, true, false, false, true, false, true, false, true]
+        );
+        assert!(line[..6].iter().all(|rs232| !rs232));
+        assert!(line[6..12].iter().all(|rs232| *rs232));
+    }
+
+    #[test]
+    fn test_uart_sender_fractional_bitlength() {
+        // 4.5 clock cycles per bit
+        let uart_sender = UartSender::new(9600 * 9, 9600 * 2);
+        let input = uart_sender
+            .test_reset()
+            .chain(uart_sender.test_set_byte(0x53))
+            .chain(repeat_n(UartSenderInput::new(), 50));
+        let line = simulate(uart_sender, input)
+            .skip(2)
+            .map(|output| output.rs232)
+            .collect_vec();
+
+        // Every bit edge is within half a cycle of its ideal position
+        let edges = (1..line.len())
+            .filter(|cycle| line[cycle - 1] != line[*cycle])
+            .collect_vec();
+        assert_eq!(edges, [5, 14, 23, 27, 32, 36, 41]);
+        assert_eq!(
+            (0..10)
+                .map(|i| line[((i as f64 + 0.5) * 4.5) as usize])
+                .collect_vec(),
+            [false, true, true, false, false, true, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn test_uart_sender_reset() {
+        let uart_sender = UartSender::new(9600 * 4, 9600);
+        // Reset in the middle of the third data bit
+        let input = uart_sender
+            .test_reset()
+            .chain(uart_sender.test_set_byte(0x00))
+            .chain(repeat_n(UartSenderInput::new(), 13))
+            .chain(std::iter::once(UartSenderInput::reset()))
+            .chain(uart_sender.test_transmission(0b01010011));
+        let outputs = simulate(uart_sender, input).skip(2).collect_vec();
+
+        assert!(outputs[..14].iter().all(|output| !output.rs232));
+        // The line goes high right away and the next byte is sent normally
+        assert!(outputs[14].rs232);
+        assert!(!outputs[14].ready);
+        assert!(outputs[15].ready);
+        let line = outputs[15..]
+            .iter()
+            .map(|output| output.rs232)
+            .collect_vec();
         assert_eq!(
-            results[start_cycle + (10 * speed as usize) - 1].0.ready,
-            false
+            (0..10).map(|i| line[i * 4 + 2]).collect_vec(),
+            [false, true, true, false, false, true, false, true, false, true]
         );
-        assert_eq!(results[start_cycle + (10 * speed as usize)].0.ready, true);
+    }
+
+    #[test]
+    fn test_uart_sender_try_new() {
+        assert!(UartSender::try_new(12_000_000, 9600).is_ok());
+        assert!(matches!(
+            UartSender::try_new(1, 9600),
+            Err(UartConfigError::DivisorTooSmall { .. })
+        ));
+        assert!(matches!(
+            UartSender::try_new(9600, 0),
+            Err(UartConfigError::ZeroBitRate)
+        ));
+        assert!(matches!(
+            UartSender::try_new(1 << 40, 9),
+            Err(UartConfigError::DivisorTooLarge { .. })
+        ));
+        assert!(matches!(
+            UartSender::try_new(9600 * 5, 9600 * 2),
+            Err(UartConfigError::BaudErrorTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 2 clock cycles per bit")]
+    fn test_uart_sender_new_without_clock_cycles() {
+        UartSender::new(1, 9600);
+    }
+
+    #[test]
+    fn test_uart_sender_handshake() {
+        let uart_sender = UartSender::new(9600 * 4, 9600);
+        // The byte is offered for longer than one frame
+        let input = uart_sender
+            .test_reset()
+            .chain(repeat_n(UartSenderInput::transmit(0x53), 42))
+            .chain(repeat_n(UartSenderInput::new(), 80));
+        let outputs = simulate(uart_sender, input).skip(2).collect_vec();
+
+        // It is taken right away and again after the stop bit
+        let taken = outputs[..42]
+            .iter()
+            .positions(|output| output.ready)
+            .collect_vec();
+        assert_eq!(taken, [0, 41]);
+        let line = outputs.iter().map(|output| output.rs232).collect_vec();
+        assert!(line[..40].iter().any(|rs232| !rs232));
+        assert!(!line[41]);
     }
 
     #[test]